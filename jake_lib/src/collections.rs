@@ -0,0 +1,278 @@
+use crate::{converters::{Converter, ConverterKind}, data_strctures::{FileSource, JakeFileT3}, error::Error, frontmatter::FrontMatter, lua::general_api::formatting, JakeConfig};
+use kstring::KString;
+use liquid::model::{DateTime, Value};
+use relative_path::{RelativePath, RelativePathBuf};
+use std::cmp::Ordering;
+
+/// Keys of the `site` object a collection can't be named after.
+pub const RESERVED_NAMES: &[&str] = &["pages", "data"];
+
+const COLLECTION_KEY: &str = "collection";
+const DATE_KEY: &str = "date";
+const DRAFT_KEY: &str = "draft";
+const EXCERPT_KEY: &str = "excerpt";
+const PATH_KEY: &str = "path";
+const SLUG_KEY: &str = "slug";
+const TITLE_KEY: &str = "title";
+const URL_KEY: &str = "url";
+
+/// A group of related source files, such as blog posts.
+#[derive(Debug, Clone, PartialEq, Eq, serde::Serialize, serde::Deserialize)]
+pub struct Collection {
+	/// The directory holding the collection, relative to the source directory.
+	pub dir: RelativePathBuf,
+	/// The page field the collection is sorted by.
+	pub sort_by: KString,
+	pub reverse: bool,
+	/// The default permalink of the collection's pages, overriding the site's.
+	pub permalink: Option<String>,
+}
+
+/// Finds the collection a file belongs to.
+/// A `collection` key in the front matter wins over the file's location, so Lua-made files can join one too.
+pub fn collection_of<'a>(config: &'a JakeConfig, source: &FileSource, front_matter: &FrontMatter) -> Option<&'a KString> {
+	if let Some(serde_json::Value::String(name)) = front_matter.get(COLLECTION_KEY) {
+		return config.collections.get_key_value(name.as_str()).map(|(name, _)| name);
+	}
+
+	let source = source.as_option()?;
+
+	config.collections.iter()
+		.find(|(_, c)| source.starts_with(&c.dir))
+		.map(|(name, _)| name)
+}
+
+/// Whether a file is marked as a draft, which only builds with drafts turned on.
+pub fn is_draft(front_matter: &FrontMatter) -> bool {
+	matches!(front_matter.get(DRAFT_KEY), Some(serde_json::Value::Bool(true)))
+}
+
+/// Finds a file's date and slug.
+///
+/// The date comes from the `date` front matter key if it parses.
+/// Files in a collection named like `2024-01-31-my-post.md` take their date and slug from the name.
+pub fn date_and_slug<'a>(collection: Option<&KString>, front_matter: &FrontMatter, path: &'a RelativePath) -> (Option<DateTime>, &'a str) {
+	let stem = path.file_stem().unwrap_or_default();
+	let (file_date, slug) = match collection.and_then(|_| split_date(stem)) {
+		Some((date, slug)) => (Some(date), slug),
+		None => (None, stem),
+	};
+
+	let date = match front_matter.get(DATE_KEY) {
+		Some(serde_json::Value::String(date)) => parse_date(date),
+		_ => None,
+	};
+
+	(date.or(file_date), slug)
+}
+
+/// Builds the object templates see as `page`, and in `site.pages` and `site.<collection>`.
+///
+/// Holds the file's front matter, plus `url`, `path`, `slug`, `title`, `date`, `collection` and `excerpt`.
+///
+/// Pages are listed before any of them render, so the excerpt is the first paragraph of the source
+/// with its Liquid taken out rather than of the rendered page.
+/// Whatever tags or output would have put there is missing from it.
+pub fn page_object(
+	config: &JakeConfig,
+	source: &FileSource,
+	output: &RelativePath,
+	front_matter: &FrontMatter,
+	content: Option<&str>,
+	converter: Option<&Converter>,
+) -> Result<liquid::Object, Error> {
+	let mut page = liquid::to_object(front_matter)?;
+
+	let path = source.as_option().map_or(output, |s| s.as_relative_path());
+	let collection = collection_of(config, source, front_matter);
+
+	let (date, slug) = date_and_slug(collection, front_matter, path);
+
+	if let Some(date) = date {
+		page.insert(DATE_KEY.into(), Value::scalar(date));
+	}
+
+	if !page.contains_key(TITLE_KEY) {
+		page.insert(TITLE_KEY.into(), Value::scalar(slug.to_owned()));
+	}
+
+	if !page.contains_key(EXCERPT_KEY) && let Some(content) = content {
+		let text = strip_liquid(content);
+		let paragraph = text.lines()
+			.skip_while(|line| line.trim().is_empty())
+			.take_while(|line| !line.trim().is_empty())
+			.collect::<Vec<_>>()
+			.join("\n");
+		let paragraph = paragraph.trim();
+
+		let excerpt = match converter {
+			Some(Converter { kind: ConverterKind::Markdown, .. }) => formatting::render_markdown(paragraph),
+			_ => paragraph.to_owned(),
+		};
+
+		page.insert(EXCERPT_KEY.into(), Value::scalar(excerpt));
+	}
+
+	page.insert(URL_KEY.into(), Value::scalar(url_for(config, output)));
+	page.insert(PATH_KEY.into(), Value::scalar(path.to_string()));
+	page.insert(SLUG_KEY.into(), Value::scalar(slug.to_owned()));
+	page.insert(COLLECTION_KEY.into(), collection.map_or(Value::Nil, |c| Value::scalar(c.clone())));
+
+	Ok(page)
+}
+
+/// The text of a template without its Liquid: tags, output, and the bodies of `comment` and `lua` blocks.
+/// What `raw` blocks hold is kept as written.
+fn strip_liquid(template: &str) -> String {
+	let mut text = String::new();
+	// The block being skipped over, whose body isn't Liquid.
+	let mut block: Option<&str> = None;
+	let mut rest = template;
+
+	while let Some(start) = [ rest.find("{{"), rest.find("{%") ].into_iter().flatten().min() {
+		let (before, markup) = rest.split_at(start);
+		let close = if markup.starts_with("{{") { "}}" } else { "%}" };
+		let Some(end) = markup.find(close) else { break };
+
+		let (markup, after) = markup.split_at(end + close.len());
+		rest = after;
+
+		let name = (close == "%}")
+			.then(|| markup[2..markup.len() - 2].trim_matches('-').split_whitespace().next())
+			.flatten();
+
+		match block {
+			Some(kind) if name.and_then(|name| name.strip_prefix("end")) == Some(kind) => {
+				if kind == "raw" {
+					text.push_str(before);
+				}
+				block = None;
+			},
+			Some("raw") => {
+				text.push_str(before);
+				text.push_str(markup);
+			},
+			Some(_) => {},
+			None => {
+				text.push_str(before);
+				if let Some(name @ ("raw" | "comment" | "lua")) = name {
+					block = Some(name);
+				}
+			},
+		}
+	}
+
+	if block.is_none_or(|kind| kind == "raw") {
+		text.push_str(rest);
+	}
+
+	text
+}
+
+/// The URL an output is served at, under the base URL.
+/// `index.html` files are linked to by their directory.
+pub fn url_for(config: &JakeConfig, output: &RelativePath) -> String {
+	match output.as_str().strip_suffix("index.html") {
+		Some(dir) if dir.is_empty() || dir.ends_with('/') => format!("{}/{dir}", config.base_url),
+		_ => format!("{}/{output}", config.base_url),
+	}
+}
+
+/// Lists the pages of the site for templates.
+///
+/// `pages` holds every HTML page outside a collection, by path.
+/// Each collection is listed under its own name, sorted as configured.
+pub fn site_pages(config: &JakeConfig, files: &[JakeFileT3]) -> liquid::Object {
+	let mut site = liquid::Object::new();
+
+	let mut pages: Vec<_> = files.iter()
+		.filter(|f| f.to_write && !f.template.is_binary() && f.collection.is_none())
+		.filter(|f| matches!(f.output.extension(), Some("html" | "htm")))
+		.map(|f| &f.page)
+		.collect();
+	pages.sort_by(|a, b| compare_field(a, b, PATH_KEY));
+
+	site.insert("pages".into(), Value::Array(pages.into_iter().cloned().map(Value::Object).collect()));
+
+	for (name, collection) in &config.collections {
+		let mut pages: Vec<_> = files.iter()
+			.filter(|f| f.to_write && f.collection.as_ref() == Some(name))
+			.map(|f| &f.page)
+			.collect();
+
+		pages.sort_by(|a, b| {
+			// Pages missing the field go last, whichever the direction.
+			let ordering = match (a.get(collection.sort_by.as_str()), b.get(collection.sort_by.as_str())) {
+				(Some(_), None) => return Ordering::Less,
+				(None, Some(_)) => return Ordering::Greater,
+				_ => compare_field(a, b, &collection.sort_by),
+			};
+
+			let ordering = if collection.reverse { ordering.reverse() } else { ordering };
+			ordering.then_with(|| compare_field(a, b, PATH_KEY))
+		});
+
+		site.insert(name.clone(), Value::Array(pages.into_iter().cloned().map(Value::Object).collect()));
+	}
+
+	site
+}
+
+fn compare_field(a: &liquid::Object, b: &liquid::Object, key: &str) -> Ordering {
+	match (a.get(key), b.get(key)) {
+		(Some(a), Some(b)) => liquid::model::ValueViewCmp::new(a).partial_cmp(&liquid::model::ValueViewCmp::new(b)).unwrap_or(Ordering::Equal),
+		_ => Ordering::Equal,
+	}
+}
+
+/// Accepts anything Liquid's `date` filter does, plus plain `YYYY-MM-DD` dates.
+fn parse_date(date: &str) -> Option<DateTime> {
+	DateTime::from_str(date).or_else(|| liquid::model::Date::from_str(date).map(|d| DateTime::default().with_date(d)))
+}
+
+/// Splits `2024-01-31-my-post` into its date and `my-post`.
+fn split_date(stem: &str) -> Option<(DateTime, &str)> {
+	let date = parse_date(stem.get(..10)?)?;
+	let slug = stem.get(10..)?.strip_prefix('-')?;
+
+	Some((date, slug))
+}
+
+#[cfg(test)]
+mod tests {
+	use super::*;
+	use liquid_core::ValueView;
+
+	#[test]
+	fn dates_in_file_names() {
+		let (date, slug) = split_date("2024-01-31-my-post").expect("Has a date");
+		assert_eq!((date.year(), date.month(), date.day()), (2024, 1, 31));
+		assert_eq!(slug, "my-post");
+
+		assert!(split_date("2024-01-31").is_none());
+		assert!(split_date("2024-01-31post").is_none());
+		assert!(split_date("2024-13-01-post").is_none());
+		assert!(split_date("my-post").is_none());
+		assert!(split_date("").is_none());
+		// Dates are cut at ten bytes, which mustn't land inside a character.
+		assert!(split_date("2024-01-3é-post").is_none());
+	}
+
+	#[test]
+	fn excerpts() {
+		let config = JakeConfig::test("/project");
+		let source = FileSource::Src(RelativePathBuf::from("_posts/2024-01-31-post.md"));
+		let excerpt = |content: &str, converter: Option<&Converter>| {
+			let page = page_object(&config, &source, RelativePath::new("post.html"), &FrontMatter::default(), Some(content), converter)
+				.expect("Page builds");
+			page.get(EXCERPT_KEY).map(|excerpt| excerpt.to_kstr().into_string())
+		};
+
+		let content = "{% assign greeting = 'Hi' %}\r\n{{ greeting }}\r\n\r\nFirst {{ page.title }}*para*\r\nstill first\r\n\r\nSecond";
+		assert_eq!(excerpt(content, None).as_deref(), Some("First *para*\nstill first"));
+		assert_eq!(excerpt(content, Some(&Converter::markdown())).as_deref(), Some("<p>First <em>para</em>\nstill first</p>"));
+
+		let content = "{% lua %}\nwrite('{{ x }}')\n\n{% endlua %}\n{%- comment -%}\n\nHidden\n{% endcomment %}\n{% raw %}{{ shown }}{% endraw %}\n\nNext";
+		assert_eq!(excerpt(content, None).as_deref(), Some("{{ shown }}"));
+	}
+}
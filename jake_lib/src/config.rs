@@ -0,0 +1,401 @@
+use crate::{collections::Collection, error::{Error, JakeError, ResultExtensions}, frontmatter::FrontMatter};
+use globset::{Glob, GlobSet, GlobSetBuilder};
+use kstring::KString;
+use relative_path::{RelativePath, RelativePathBuf};
+use std::{collections::BTreeMap, path::{Path, PathBuf}};
+
+pub const CONFIG_FILE: &str = "jake.yml";
+/// Front matter for the files of a source directory, keyed by glob.
+pub const DEFAULT_FRONTMATTER_FILE: &str = ".jake.yml";
+
+#[derive(Debug, Clone, PartialEq, Eq, serde::Serialize, serde::Deserialize)]
+pub struct JakeConfig {
+	pub project_dir: PathBuf,
+	pub output_dir: PathBuf,
+	pub source_dir: PathBuf,
+	pub plugins_dir: PathBuf,
+	pub layout_dir: PathBuf,
+	pub includes_dir: PathBuf,
+	pub data_dir: PathBuf,
+	/// Where Jake keeps what it needs between builds, such as the dependency graph for incremental builds.
+	pub cache_dir: PathBuf,
+	/// Directories searched by Sass `@use` and `@import`.
+	pub sass_load_paths: Vec<PathBuf>,
+	/// Source files to skip, relative to `source_dir`.
+	pub exclude: Vec<Glob>,
+	/// Source files to keep even if they're hidden or excluded, relative to `source_dir`.
+	pub include: Vec<Glob>,
+	/// Outputs never deleted for going stale, relative to `output_dir`.
+	pub keep_files: Vec<Glob>,
+	/// The path the site is served under, e.g. `/blog`, or empty if it's served from the root.
+	pub base_url: String,
+	/// Front matter for source files matching each glob, applied before any `.jake.yml`.
+	pub defaults: Vec<(Glob, FrontMatter)>,
+	/// The default permalink of HTML pages.
+	pub permalink: Option<String>,
+	/// Groups of source files listed together in templates as `site.<name>`.
+	pub collections: BTreeMap<KString, Collection>,
+	/// Every key of `jake.yml` that isn't a Jake setting, exposed to templates as globals.
+	pub globals: FrontMatter,
+}
+
+/// The settings Jake understands in `jake.yml`.
+/// Paths are relative to the project directory.
+#[derive(Debug, Default, serde::Deserialize)]
+#[serde(default)]
+struct ConfigFile {
+	source_dir: Option<PathBuf>,
+	output_dir: Option<PathBuf>,
+	plugins_dir: Option<PathBuf>,
+	layout_dir: Option<PathBuf>,
+	includes_dir: Option<PathBuf>,
+	data_dir: Option<PathBuf>,
+	cache_dir: Option<PathBuf>,
+	sass_load_paths: Option<Vec<PathBuf>>,
+	exclude: Vec<String>,
+	include: Vec<String>,
+	keep_files: Vec<String>,
+	base_url: String,
+	defaults: BTreeMap<String, FrontMatter>,
+	permalink: Option<String>,
+	collections: BTreeMap<KString, CollectionFile>,
+	#[serde(flatten)]
+	globals: FrontMatter,
+}
+
+/// A collection as written in `jake.yml`.
+/// `dir` is relative to the source directory and defaults to `_<name>`.
+#[derive(Debug, Default, serde::Deserialize)]
+#[serde(default)]
+struct CollectionFile {
+	dir: Option<RelativePathBuf>,
+	sort_by: Option<KString>,
+	reverse: bool,
+	permalink: Option<String>,
+}
+
+impl JakeConfig {
+	/// Reads `jake.yml` from `project_dir`.
+	/// `output_override` replaces the configured output directory, as given on the command line.
+	pub fn load(project_dir: impl Into<PathBuf>, output_override: Option<PathBuf>) -> Result<Self, Error> {
+		let project_dir = project_dir.into();
+
+		let file: ConfigFile = serde_yaml::from_str(&std::fs::read_to_string(project_dir.join(CONFIG_FILE))?)
+			.into_error_result_with(CONFIG_FILE)?;
+
+		let dir = |path: Option<PathBuf>, default: &str| project_dir.join(path.unwrap_or_else(|| PathBuf::from(default)));
+
+		let config = JakeConfig {
+			source_dir: dir(file.source_dir, "src"),
+			output_dir: output_override.unwrap_or_else(|| dir(file.output_dir, "site")),
+			plugins_dir: dir(file.plugins_dir, "plugins"),
+			layout_dir: dir(file.layout_dir, "layouts"),
+			includes_dir: dir(file.includes_dir, "_includes"),
+			data_dir: dir(file.data_dir, "_data"),
+			cache_dir: dir(file.cache_dir, ".jake-cache"),
+			sass_load_paths: file.sass_load_paths.map_or_else(
+				|| vec![project_dir.join("_sass")],
+				|paths| paths.into_iter().map(|p| project_dir.join(p)).collect(),
+			),
+			exclude: file.exclude.iter().map(|g| build_glob(g)).try_collect().into_error_result_with(CONFIG_FILE)?,
+			include: file.include.iter().map(|g| build_glob(g)).try_collect().into_error_result_with(CONFIG_FILE)?,
+			keep_files: file.keep_files.iter().map(|g| build_glob(g)).try_collect().into_error_result_with(CONFIG_FILE)?,
+			base_url: file.base_url.trim_end_matches('/').to_owned(),
+			defaults: file.defaults.into_iter()
+				.map(|(glob, fm)| Ok::<_, Error>((build_glob(&glob)?, fm)))
+				.try_collect()
+				.into_error_result_with(CONFIG_FILE)?,
+			permalink: file.permalink,
+			collections: file.collections.into_iter()
+				.map(|(name, c)| {
+					let collection = Collection {
+						dir: c.dir.unwrap_or_else(|| RelativePathBuf::from(format!("_{name}"))),
+						sort_by: c.sort_by.unwrap_or_else(|| KString::from_static("date")),
+						reverse: c.reverse,
+						permalink: c.permalink,
+					};
+
+					(name, collection)
+				})
+				.collect(),
+			globals: file.globals,
+			project_dir,
+		};
+
+		config.validate().into_error_result_with(CONFIG_FILE)?;
+
+		Ok(config)
+	}
+
+	fn validate(&self) -> Result<(), JakeError> {
+		use JakeError::InvalidConfig;
+
+		if !self.source_dir.is_dir() {
+			return Err(InvalidConfig("source_dir", format!("'{}' is not a directory", self.source_dir.display())));
+		}
+
+		let output_dir = normalize(&self.output_dir);
+		if normalize(&self.source_dir).starts_with(&output_dir) || normalize(&self.project_dir).starts_with(&output_dir) {
+			return Err(InvalidConfig("output_dir", format!("'{}' would contain the project's sources", self.output_dir.display())));
+		}
+
+		if !self.base_url.is_empty() && !self.base_url.starts_with('/') {
+			return Err(InvalidConfig("base_url", format!("'{}' must start with '/'", self.base_url)));
+		}
+
+		if let Some(name) = self.collections.keys().find(|name| crate::collections::RESERVED_NAMES.contains(&name.as_str())) {
+			return Err(InvalidConfig("collections", format!("'{name}' is reserved for Jake's own use")));
+		}
+
+		Ok(())
+	}
+
+	/// Compiles the `exclude` and `include` globs.
+	pub fn source_filter(&self) -> Result<SourceFilter, Error> {
+		let set = |globs: &[Glob]| globs.iter()
+			.fold(GlobSetBuilder::new(), |mut builder, glob| { builder.add(glob.clone()); builder })
+			.build();
+
+		Ok(SourceFilter {
+			exclude: set(&self.exclude)?,
+			include: set(&self.include)?,
+			generated: vec![ self.output_dir.clone(), self.cache_dir.clone() ],
+		})
+	}
+
+	/// Compiles the rules for which changes to the project are worth a rebuild.
+	pub fn watch_filter(&self) -> Result<WatchFilter, Error> {
+		Ok(WatchFilter {
+			project_dir: self.project_dir.clone(),
+			source_dir: self.source_dir.clone(),
+			sources: self.source_filter()?,
+		})
+	}
+}
+
+/// Decides which entries of the source directory take part in a build.
+#[derive(Debug, Clone)]
+pub struct SourceFilter {
+	exclude: GlobSet,
+	include: GlobSet,
+	/// What Jake writes itself.
+	generated: Vec<PathBuf>,
+}
+
+impl SourceFilter {
+	/// Hidden entries, starting with `.`, are ignored unless included.
+	/// Hidden directories must be included themselves for their contents to be reachable.
+	pub fn is_ignored(&self, rel_path: &RelativePath) -> bool {
+		if self.include.is_match(rel_path.as_str()) {
+			return false;
+		}
+
+		let hidden = rel_path.file_name().is_some_and(|name| name.starts_with('.'));
+
+		hidden || self.exclude.is_match(rel_path.as_str())
+	}
+
+	/// Whether `path` is in the output or cache directory, which may well sit in the source directory.
+	pub fn is_generated(&self, path: &Path) -> bool {
+		self.generated.iter().any(|dir| path.starts_with(dir))
+	}
+}
+
+/// Decides which changed paths matter to a build, seeing the project the way a build does.
+#[derive(Debug, Clone)]
+pub struct WatchFilter {
+	project_dir: PathBuf,
+	source_dir: PathBuf,
+	sources: SourceFilter,
+}
+
+impl WatchFilter {
+	/// Ignores the output and cache directories, editor temporary files, and anything hidden.
+	/// Sources are ignored like a build ignores them, by the `exclude` and `include` globs.
+	pub fn is_ignored(&self, path: &Path) -> bool {
+		if self.sources.is_generated(path) {
+			return true;
+		}
+
+		let Some(name) = path.file_name().map(|n| n.to_string_lossy()) else {
+			return false;
+		};
+
+		if name == DEFAULT_FRONTMATTER_FILE {
+			return false;
+		}
+
+		if is_temp_file(&name) {
+			return true;
+		}
+
+		let rel_path = |dir: &Path| path.strip_prefix(dir).ok().and_then(|p| RelativePathBuf::from_path(p).ok());
+
+		// A build skips everything under an ignored directory too.
+		if let Some(rel_path) = rel_path(&self.source_dir) {
+			return std::iter::successors(Some(rel_path.as_relative_path()), |p| p.parent())
+				.filter(|p| !p.as_str().is_empty())
+				.any(|p| self.sources.is_ignored(p));
+		}
+
+		rel_path(&self.project_dir)
+			.is_some_and(|p| p.components().any(|c| c.as_str().starts_with('.')))
+	}
+}
+
+/// `path` made absolute with any `.` and `..` worked out, so paths written differently compare the same.
+/// Symbolic links are left alone, as the output directory needn't exist yet.
+fn normalize(path: &Path) -> PathBuf {
+	let path = std::path::absolute(path).unwrap_or_else(|_| path.to_owned());
+
+	path.components().fold(PathBuf::new(), |mut normalized, component| {
+		match component {
+			std::path::Component::CurDir => {},
+			std::path::Component::ParentDir => { normalized.pop(); },
+			component => normalized.push(component),
+		}
+
+		normalized
+	})
+}
+
+/// Swap, backup and lock files editors keep next to the files being edited.
+fn is_temp_file(name: &str) -> bool {
+	let swap = [ ".swp", ".swo", ".swx" ].iter().any(|ext| name.ends_with(ext));
+	let jetbrains = name.ends_with("___jb_tmp___") || name.ends_with("___jb_old___");
+	// Vim checks it can write to a directory by making a file named `4913`.
+	let vim_probe = name == "4913";
+	let emacs = name.starts_with(".#") || (name.starts_with('#') && name.ends_with('#'));
+
+	swap || jetbrains || vim_probe || emacs || name.ends_with('~')
+}
+
+pub(crate) fn build_glob(glob: &str) -> Result<Glob, globset::Error> {
+	globset::GlobBuilder::new(glob)
+		.backslash_escape(true)
+		.empty_alternates(true)
+		.build()
+}
+
+#[cfg(test)]
+impl JakeConfig {
+	/// A project in `project_dir` with every setting left at its default, without reading anything from disk.
+	pub(crate) fn test(project_dir: impl Into<PathBuf>) -> Self {
+		let project_dir = project_dir.into();
+
+		JakeConfig {
+			source_dir: project_dir.join("src"),
+			output_dir: project_dir.join("site"),
+			plugins_dir: project_dir.join("plugins"),
+			layout_dir: project_dir.join("layouts"),
+			includes_dir: project_dir.join("_includes"),
+			data_dir: project_dir.join("_data"),
+			cache_dir: project_dir.join(".jake-cache"),
+			sass_load_paths: vec![ project_dir.join("_sass") ],
+			exclude: Vec::new(),
+			include: Vec::new(),
+			keep_files: Vec::new(),
+			base_url: String::new(),
+			defaults: Vec::new(),
+			permalink: None,
+			collections: BTreeMap::new(),
+			globals: FrontMatter::default(),
+			project_dir,
+		}
+	}
+}
+
+#[cfg(test)]
+mod tests {
+	use super::*;
+
+	#[test]
+	fn temp_files() {
+		for name in [ ".#index.md", "#index.md#", "index.md~", ".index.md.swp", ".index.md.swo", "index.md___jb_tmp___", "4913" ] {
+			assert!(is_temp_file(name), "{name} is a temporary file");
+		}
+
+		for name in [ "index.md", "#index.md", "post#1.md", "swp", "49130" ] {
+			assert!(!is_temp_file(name), "{name} isn't a temporary file");
+		}
+	}
+
+	#[test]
+	fn watch_filter() {
+		let mut config = JakeConfig::test("/project");
+		config.output_dir = PathBuf::from("/project/src/_site");
+		config.exclude = vec![ build_glob("drafts/**").expect("Valid glob") ];
+		let filter = config.watch_filter().expect("Valid filter");
+
+		let ignored = |path: &str| filter.is_ignored(Path::new(path));
+
+		assert!(!ignored("/project/src/index.md"));
+		assert!(!ignored("/project/src/blog/.jake.yml"));
+		assert!(!ignored("/project/layouts/base.html"));
+
+		assert!(ignored("/project/src/_site/index.html"));
+		assert!(ignored("/project/.jake-cache/graph"));
+		assert!(ignored("/project/src/.#index.md"));
+		assert!(ignored("/project/src/#index.md#"));
+		assert!(ignored("/project/src/index.md~"));
+		assert!(ignored("/project/src/.index.md.swp"));
+		assert!(ignored("/project/src/drafts/post.md"));
+		assert!(ignored("/project/src/.hidden/page.md"));
+		assert!(ignored("/project/.git/HEAD"));
+	}
+
+	fn load(name: &str, config: &str, output_override: Option<&str>) -> Result<JakeConfig, Error> {
+		let project_dir = std::env::temp_dir().join(format!("jake-config-{name}-{}", std::process::id()));
+		std::fs::create_dir_all(project_dir.join("src"))?;
+		std::fs::write(project_dir.join(CONFIG_FILE), config)?;
+
+		let result = JakeConfig::load(&project_dir, output_override.map(|dir| project_dir.join(dir)));
+		let _ = std::fs::remove_dir_all(&project_dir);
+
+		result
+	}
+
+	fn invalid_key(result: &Result<JakeConfig, Error>) -> Option<&'static str> {
+		match result.as_ref().map_err(Error::context_chain) {
+			Err((_, Error::JakeError(JakeError::InvalidConfig(key, _)))) => Some(key),
+			_ => None,
+		}
+	}
+
+	#[test]
+	fn globals() {
+		let config = load("globals", "title: Site\nauthor:\n  name: Pengu\nbase_url: /blog/\npermalink: pretty\n", None).expect("Config loads");
+
+		assert_eq!(config.base_url, "/blog");
+		assert_eq!(config.permalink.as_deref(), Some("pretty"));
+		assert_eq!(config.globals.keys().map(|key| key.as_str()).collect::<Vec<_>>(), [ "author", "title" ]);
+		assert_eq!(config.globals.get("author"), Some(&serde_json::json!({ "name": "Pengu" })));
+	}
+
+	#[test]
+	fn output_dirs() {
+		for (name, config, output_override) in [
+			("project", "output_dir: .", None),
+			("source", "output_dir: src", None),
+			("source-dots", "output_dir: ./src/../src", None),
+			("override", "", Some("src")),
+			("override-dots", "", Some("site/../src/")),
+			("parent", "output_dir: ..", None),
+		] {
+			let result = load(name, config, output_override);
+			assert_eq!(invalid_key(&result), Some("output_dir"), "{name} should be rejected, got {result:?}");
+		}
+
+		assert!(load("inside", "output_dir: src/_site", None).is_ok());
+		assert!(load("dots", "", Some("src/../site")).is_ok());
+	}
+
+	#[test]
+	fn invalid_config() {
+		assert!(load("glob", "exclude: [ 'a/[b' ]", None).is_err());
+		assert!(load("default-glob", "defaults: { 'a/{b': { layout: x } }", None).is_err());
+		assert_eq!(invalid_key(&load("base-url", "base_url: blog", None)), Some("base_url"));
+		assert_eq!(invalid_key(&load("collection", "collections: { pages: {} }", None)), Some("collections"));
+		assert!(load("yaml", "exclude: 1", None).is_err());
+	}
+}
@@ -0,0 +1,177 @@
+use crate::{error::{Error, ResultExtensions}, lua::general_api::formatting::{self, SassOptions}, JakeConfig};
+use kstring::KString;
+use mlua::{FromLua, Lua};
+use std::{collections::HashMap, path::Path};
+
+const FUNC_FIELD: &str = "func";
+const EXT_FIELD: &str = "ext";
+
+/// A conversion step keyed by source extension.
+///
+/// Converters run after a file's Liquid template has been rendered,
+/// but before its post-processors and layouts.
+#[derive(Debug, Clone)]
+pub struct Converter {
+	/// The extension given to converted files, without leading dot.
+	/// `None` leaves the output extension untouched.
+	pub ext: Option<KString>,
+	pub kind: ConverterKind,
+}
+
+#[derive(Debug, Clone)]
+pub enum ConverterKind {
+	Markdown,
+	Sass(SassOptions),
+	Lua(mlua::Function),
+}
+
+impl Converter {
+	pub fn markdown() -> Self {
+		Self { ext: Some(KString::from_static("html")), kind: ConverterKind::Markdown }
+	}
+
+	pub fn sass(options: SassOptions) -> Self {
+		Self { ext: Some(KString::from_static("css")), kind: ConverterKind::Sass(options) }
+	}
+
+	/// Converts rendered content.
+	///
+	/// `source` is the absolute path of the file being converted, if it has one.  
+	/// `info` is the same table handed to post-processors.
+	pub fn convert(&self, content: &str, source: Option<&Path>, info: &mlua::Table) -> Result<String, Error> {
+		match &self.kind {
+			ConverterKind::Markdown => Ok(formatting::render_markdown(content)),
+			ConverterKind::Sass(options) => {
+				// Relative imports resolve against the importing file first.
+				let mut options = options.clone();
+				if let Some(dir) = source.and_then(Path::parent) {
+					options.load_paths.insert(0, dir.to_owned());
+				}
+
+				Ok(formatting::compile_sass(content, &options)?)
+			},
+			ConverterKind::Lua(func) => {
+				let context = || {
+					let mlua::FunctionInfo { short_src, line_defined, .. } = func.info();
+					let src = short_src.unwrap_or_else(|| String::from("Unknown"));
+					let line = line_defined.map_or(String::new(), |l| format!(":{l}"));
+
+					format!("Converter function: {src}{line}")
+				};
+
+				let result: mlua::String = func.call((content, info.clone())).into_error_result_with(context)?;
+				Ok(result.to_str()?.to_owned())
+			},
+		}
+	}
+}
+
+impl FromLua for Converter {
+	fn from_lua(value: mlua::Value, _: &Lua) -> mlua::Result<Self> {
+		match value {
+			mlua::Value::Function(func) => Ok(Self { ext: None, kind: ConverterKind::Lua(func) }),
+			mlua::Value::Table(table) => Ok(Self {
+				ext: table.get::<Option<String>>(EXT_FIELD)?.map(KString::from),
+				kind: ConverterKind::Lua(table.get(FUNC_FIELD)?),
+			}),
+			value => Err(mlua::Error::runtime(format!("Expected a function or a table for a converter, got {:?}", value.type_name()))),
+		}
+	}
+}
+
+/// The set of converters known to a build, keyed by source extension.
+#[derive(Debug, Clone)]
+pub struct Converters(HashMap<KString, Converter>);
+
+impl Converters {
+	/// The built-in converters.
+	pub fn with_builtins(config: &JakeConfig) -> Self {
+		let mut converters = HashMap::new();
+
+		converters.insert(KString::from_static("md"), Converter::markdown());
+		converters.insert(KString::from_static("markdown"), Converter::markdown());
+
+		let sass = SassOptions { load_paths: config.sass_load_paths.clone(), ..Default::default() };
+		converters.insert(KString::from_static("scss"), Converter::sass(sass.clone()));
+		converters.insert(KString::from_static("sass"), Converter::sass(SassOptions { indented: true, ..sass }));
+
+		Self(converters)
+	}
+
+	pub fn get(&self, ext: &str) -> Option<&Converter> {
+		self.0.get(ext)
+	}
+
+	/// Registers a converter, replacing any existing one for `ext`.
+	/// A replacement without an explicit output extension keeps the one of the converter it replaces.
+	pub fn register(&mut self, ext: impl Into<KString>, mut converter: Converter) {
+		let ext = ext.into();
+
+		if converter.ext.is_none() && let Some(existing) = self.0.get(&ext) {
+			converter.ext = existing.ext.clone();
+		}
+
+		self.0.insert(ext, converter);
+	}
+
+	pub fn remove(&mut self, ext: &str) {
+		self.0.remove(ext);
+	}
+}
+
+/// Sass partials are only ever imported, never compiled on their own.
+pub fn is_sass_partial(path: &Path) -> bool {
+	let is_sass = path.extension().is_some_and(|ext| ext == "scss" || ext == "sass");
+	let is_partial = path.file_name().is_some_and(|name| name.to_string_lossy().starts_with('_'));
+
+	is_sass && is_partial
+}
+
+#[cfg(test)]
+mod tests {
+	use super::*;
+
+	#[test]
+	fn converting() {
+		let lua = Lua::new();
+		let info = lua.create_table().expect("Table is created");
+		info.set("is_final", true).expect("Field is set");
+
+		let output = Converter::markdown().convert("*hi*", None, &info).expect("Markdown converts");
+		assert_eq!(output, "<p><em>hi</em></p>");
+
+		let func = lua.load("function(content, info) return content:upper() .. tostring(info.is_final) end").eval().expect("Function loads");
+		let output = Converter::from_lua(func, &lua).expect("Converter loads").convert("hi ", None, &info).expect("Lua converts");
+		assert_eq!(output, "HI true");
+
+		let func: mlua::Function = lua.load("function() error('nope') end").eval().expect("Function loads");
+		assert!(Converter { ext: None, kind: ConverterKind::Lua(func) }.convert("", None, &info).is_err());
+	}
+
+	#[test]
+	fn registering() {
+		let lua = Lua::new();
+		let func = || lua.load("function(content) return content end").eval::<mlua::Function>().expect("Function loads");
+
+		let mut converters = Converters::with_builtins(&JakeConfig::test("/project"));
+		converters.register("md", Converter { ext: None, kind: ConverterKind::Lua(func()) });
+		converters.register("txt", Converter { ext: None, kind: ConverterKind::Lua(func()) });
+		converters.register("scss", Converter { ext: Some("min.css".into()), kind: ConverterKind::Lua(func()) });
+		converters.remove("sass");
+
+		let ext = |ext: &str| converters.get(ext).map(|c| c.ext.as_ref().map(|ext| ext.as_str()));
+		assert_eq!(ext("md"), Some(Some("html")));
+		assert_eq!(ext("txt"), Some(None));
+		assert_eq!(ext("scss"), Some(Some("min.css")));
+		assert_eq!(ext("sass"), None);
+		assert!(matches!(converters.get("md"), Some(Converter { kind: ConverterKind::Lua(_), .. })));
+	}
+
+	#[test]
+	fn sass_partials() {
+		assert!(is_sass_partial(Path::new("css/_base.scss")));
+		assert!(is_sass_partial(Path::new("_base.sass")));
+		assert!(!is_sass_partial(Path::new("css/main.scss")));
+		assert!(!is_sass_partial(Path::new("_base.css")));
+	}
+}
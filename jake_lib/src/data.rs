@@ -0,0 +1,102 @@
+use crate::{error::{Error, JakeError, ResultExtensions}, JakeConfig};
+use serde_json::{Map, Value};
+use std::path::Path;
+
+/// Loads every data file in the data directory into a nested object.
+///
+/// Files are keyed by their path relative to the data directory, without extension,
+/// so `menus/footer.yml` ends up at `data.menus.footer`.
+/// Files with unknown extensions are skipped.
+pub fn collect_data(config: &JakeConfig) -> Result<Map<String, Value>, Error> {
+	let JakeConfig { data_dir, .. } = config;
+
+	let mut data = Map::new();
+
+	if !data_dir.exists() {
+		return Ok(data);
+	}
+
+	for entry in crate::walk_files(data_dir) {
+		let (entry, rel_path) = entry?;
+
+		let Some(value) = parse_data_file(entry.path()).into_error_result_with(|| rel_path.as_str())? else {
+			continue;
+		};
+
+		let mut keys = rel_path.with_extension("").components()
+			.map(|c| c.as_str().to_owned())
+			.collect::<Vec<_>>();
+		let Some(last) = keys.pop() else {
+			continue;
+		};
+
+		let mut table = &mut data;
+		for key in keys {
+			let Value::Object(map) = table.entry(key).or_insert_with(|| Value::Object(Default::default())) else {
+				return Err(JakeError::DataConflict(rel_path).into());
+			};
+
+			table = map;
+		}
+
+		if table.insert(last, value).is_some() {
+			return Err(JakeError::DataConflict(rel_path).into());
+		}
+	}
+
+	Ok(data)
+}
+
+/// Parses a single data file by its extension.
+/// Returns `Ok(None)` if the extension isn't a known data format.
+fn parse_data_file(path: &Path) -> Result<Option<Value>, Error> {
+	let Some(ext) = path.extension().and_then(|e| e.to_str()) else {
+		return Ok(None);
+	};
+
+	let value = match ext {
+		"yml" | "yaml" => serde_yaml::from_str(&std::fs::read_to_string(path)?)?,
+		"json" => serde_json::from_str(&std::fs::read_to_string(path)?)?,
+		"toml" => toml::from_str(&std::fs::read_to_string(path)?)?,
+		"csv" => {
+			// Each row becomes an object keyed by the header row, like Jekyll does.
+			let mut reader = csv::Reader::from_path(path)?;
+			let rows: Vec<Map<String, Value>> = reader.deserialize().try_collect()?;
+
+			Value::Array(rows.into_iter().map(Value::Object).collect())
+		},
+		_ => return Ok(None),
+	};
+
+	Ok(Some(value))
+}
+
+#[cfg(test)]
+mod tests {
+	use super::*;
+	use serde_json::json;
+
+	#[test]
+	fn csv_and_toml() {
+		let project_dir = std::env::temp_dir().join(format!("jake-data-{}", std::process::id()));
+		let config = JakeConfig::test(&project_dir);
+
+		std::fs::create_dir_all(config.data_dir.join("menus")).expect("Temp dir is writable");
+		std::fs::write(config.data_dir.join("authors.csv"), "name,site\nAda,ada.dev\nGrace,\n").expect("Temp dir is writable");
+		std::fs::write(config.data_dir.join("menus/footer.toml"), "title = \"Footer\"\n[[links]]\nurl = \"/about\"\n").expect("Temp dir is writable");
+		std::fs::write(config.data_dir.join("notes.txt"), "Not data").expect("Temp dir is writable");
+
+		let data = collect_data(&config);
+		std::fs::remove_dir_all(&project_dir).expect("Temp dir is removable");
+
+		assert_eq!(Value::Object(data.expect("Data is valid")), json!({
+			"authors": [
+				{ "name": "Ada", "site": "ada.dev" },
+				{ "name": "Grace", "site": "" },
+			],
+			"menus": {
+				"footer": { "title": "Footer", "links": [ { "url": "/about" } ] },
+			},
+		}));
+	}
+}
@@ -1,8 +1,8 @@
-use crate::frontmatter::FrontMatter;
+use crate::{converters::Converter, frontmatter::FrontMatter};
+use kstring::KString;
 use relative_path::RelativePathBuf;
-use std::path::PathBuf;
 
-#[derive(Debug, Clone, Default, serde::Serialize, serde::Deserialize)]
+#[derive(Debug, Clone, Default, PartialEq, serde::Serialize, serde::Deserialize)]
 pub struct JakeFileT1 {
 	pub source: RelativePathBuf,
 	pub front_matter: FrontMatter,
@@ -27,19 +27,16 @@ pub struct JakeFileT3 {
 	pub front_matter: FrontMatter,
 	pub template: FileContent<liquid::Template>,
 	pub to_write: bool,
+	pub converter: Option<Converter>,
 	pub post_processor: Vec<mlua::Function>,
+	pub collection: Option<KString>,
+	/// Hash of the file as Lua left it, for incremental builds.
+	pub hash: u64,
+	/// The `page` object templates see for this file.
+	pub page: liquid::Object,
 }
 
-#[derive(Debug, Clone, PartialEq, Eq, Hash, serde::Serialize, serde::Deserialize)]
-pub struct JakeConfig {
-	pub project_dir: PathBuf,
-	pub output_dir: PathBuf,
-	pub source_dir: PathBuf,
-	pub plugins_dir: PathBuf,
-	pub layout_dir: PathBuf,
-}
-
-#[derive(Debug, Clone, serde::Serialize, serde::Deserialize)]
+#[derive(Debug, Clone, PartialEq, serde::Serialize, serde::Deserialize)]
 #[serde(bound = "T: Clone + serde::Serialize + for<'a> serde::Deserialize<'a>")]
 #[serde(into = "Option<T>", from = "Option<T>")]
 pub enum FileContent<T> {
@@ -97,6 +94,15 @@ pub enum FileSource<T = RelativePathBuf> {
 	Lua,
 }
 
+impl<T: std::fmt::Display> std::fmt::Display for FileSource<T> {
+	fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+		match self {
+			Self::Src(p) => write!(f, "'{p}'"),
+			Self::Lua => write!(f, "a file made in Lua"),
+		}
+	}
+}
+
 impl<T> From<Option<T>> for FileSource<T> {
 	fn from(opt: Option<T>) -> Self {
 		match opt {
@@ -1,3 +1,4 @@
+use crate::data_strctures::FileSource;
 use kstring::KString;
 use relative_path::RelativePathBuf;
 use std::path::PathBuf;
@@ -5,8 +6,16 @@ use std::path::PathBuf;
 #[derive(Debug, Clone)]
 pub enum JakeError {
 	LayoutNotFound(KString),
+	AmbiguousLayout(KString, Vec<RelativePathBuf>),
+	LayoutCycle(Vec<RelativePathBuf>),
+	PartialNotFound(KString),
 	FileNotUtf8(RelativePathBuf),
 	UnexpectedFilePath(PathBuf),
+	InvalidConfig(&'static str, String),
+	DataConflict(RelativePathBuf),
+	InvalidFrontMatter(&'static str, String),
+	OutputCollision(RelativePathBuf, Vec<FileSource>),
+	OutputOutsideDir(RelativePathBuf, FileSource),
 	Misc(&'static str),
 }
 
@@ -16,8 +25,37 @@ impl std::fmt::Display for JakeError {
 	fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
 		match self {
 			JakeError::LayoutNotFound(path) => write!(f, "Layout not found: '{path}'"),
+			JakeError::AmbiguousLayout(name, paths) => {
+				write!(f, "Layout '{name}' is ambiguous, it could be any of: ")?;
+				for (i, path) in paths.iter().enumerate() {
+					if i > 0 { write!(f, ", ")?; }
+					write!(f, "'{path}'")?;
+				}
+				Ok(())
+			},
+			JakeError::LayoutCycle(paths) => {
+				write!(f, "Layouts wrap each other in a cycle: ")?;
+				for (i, path) in paths.iter().enumerate() {
+					if i > 0 { write!(f, " -> ")?; }
+					write!(f, "'{path}'")?;
+				}
+				Ok(())
+			},
+			JakeError::PartialNotFound(path) => write!(f, "Partial not found: '{path}'"),
 			JakeError::FileNotUtf8(path) => write!(f, "File is not valid UTF-8: '{path}'"),
 			JakeError::UnexpectedFilePath(path) => write!(f, "BUG: File not in expected directory: '{}'", path.display()),
+			JakeError::InvalidConfig(key, reason) => write!(f, "Invalid value for '{key}': {reason}"),
+			JakeError::DataConflict(path) => write!(f, "Data file clashes with another file or directory of the same name: '{path}'"),
+			JakeError::InvalidFrontMatter(key, reason) => write!(f, "Invalid front matter '{key}': {reason}"),
+			JakeError::OutputCollision(path, sources) => {
+				write!(f, "Multiple files write to '{path}': ")?;
+				for (i, source) in sources.iter().enumerate() {
+					if i > 0 { write!(f, ", ")?; }
+					write!(f, "{source}")?;
+				}
+				Ok(())
+			},
+			JakeError::OutputOutsideDir(path, source) => write!(f, "Output '{path}' of {source} is outside the output directory"),
 			JakeError::Misc(e) => write!(f, "BUG: Unknown error: '{e}'"),
 		}
 	}
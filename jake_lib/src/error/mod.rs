@@ -60,7 +60,9 @@ impl<P: Into<String>, E: Into<Error>> From<(P, E)> for Error {
 #[derive(Debug, Clone)]
 pub enum SerdeError {
 	Json(Arc<serde_json::Error>),
-	Yaml(Arc<serde_yaml::Error>)
+	Yaml(Arc<serde_yaml::Error>),
+	Toml(Arc<toml::de::Error>),
+	Csv(Arc<csv::Error>),
 }
 
 impl From<serde_json::Error> for SerdeError {
@@ -71,6 +73,14 @@ impl From<serde_yaml::Error> for SerdeError {
 	fn from(e: serde_yaml::Error) -> Self { SerdeError::Yaml(e.into()) }
 }
 
+impl From<toml::de::Error> for SerdeError {
+	fn from(e: toml::de::Error) -> Self { SerdeError::Toml(e.into()) }
+}
+
+impl From<csv::Error> for SerdeError {
+	fn from(e: csv::Error) -> Self { SerdeError::Csv(e.into()) }
+}
+
 impl Error {
 	/// If this error is a Liquid or Lua error containing a
 	/// dynamic Error, recursively downcast it to that Error.  
@@ -88,6 +98,19 @@ impl Error {
 		}
 	}
 
+	/// Splits the file context off an error, outermost first, from the error beneath it.
+	pub fn context_chain(&self) -> (Vec<&str>, &Error) {
+		let mut contexts = Vec::new();
+		let mut error = self;
+
+		while let Error::WithContext { context, error: inner } = error {
+			contexts.push(context.as_str());
+			error = inner;
+		}
+
+		(contexts, error)
+	}
+
 	pub fn print_error(self) {
 		eprintln!("{}", self/* .downcast() */);
 	}
@@ -103,6 +126,8 @@ impl Display for Error {
 			Error::Io(e) => write!(f, "IO error: {e}"),
 			Error::Serde(SerdeError::Json(e)) => write!(f, "JSON error: {e}"),
 			Error::Serde(SerdeError::Yaml(e)) => write!(f, "YAML error: {e}"),
+			Error::Serde(SerdeError::Toml(e)) => write!(f, "TOML error: {e}"),
+			Error::Serde(SerdeError::Csv(e)) => write!(f, "CSV error: {e}"),
 			Error::Glob(e) => write!(f, "Glob pattern error: {e}"),
 			Error::JakeError(e) => write!(f, "Jake error: {e}"),
 			Error::WithContext { context, error } => write!(f, "{error} (context - {context})"),
@@ -0,0 +1,410 @@
+use crate::{error::{Error, ResultExtensions}, JakeConfig};
+use kstring::KStringCow;
+use liquid::model::{DisplayCow, ObjectView, State, Value, ValueView};
+use relative_path::RelativePathBuf;
+use std::{cell::RefCell, collections::{BTreeMap, BTreeSet, HashSet}, path::{Path, PathBuf}};
+
+const GRAPH_FILE: &str = "deps.json";
+
+/// What a single output was built from.
+#[derive(Debug, Clone, Default, PartialEq, Eq, serde::Serialize, serde::Deserialize)]
+pub struct OutputDeps {
+	/// Hash of the file as Lua left it: its source, output, front matter and content.
+	pub file: u64,
+	/// Layouts and partials used while rendering.
+	pub files: BTreeSet<PathBuf>,
+	/// Dotted paths of the globals read while rendering, such as `site.data.authors`.
+	pub globals: BTreeSet<String>,
+}
+
+/// The dependencies of every output of a build, and the hashes they had at the time.
+#[derive(Debug, Clone, Default, serde::Serialize, serde::Deserialize)]
+pub struct DepGraph {
+	/// Hash of `jake.yml` and the plugins, which can change any output.
+	pub project: u64,
+	pub files: BTreeMap<PathBuf, u64>,
+	pub globals: BTreeMap<String, u64>,
+	pub outputs: BTreeMap<RelativePathBuf, OutputDeps>,
+}
+
+impl DepGraph {
+	/// Reads the graph of the last build, if there is a usable one.
+	pub fn load(config: &JakeConfig) -> Option<Self> {
+		let file = std::fs::File::open(config.cache_dir.join(GRAPH_FILE)).ok()?;
+		serde_json::from_reader(std::io::BufReader::new(file)).ok()
+	}
+
+	pub fn save(&self, config: &JakeConfig) -> Result<(), Error> {
+		std::fs::create_dir_all(&config.cache_dir)?;
+
+		let path = config.cache_dir.join(GRAPH_FILE);
+		std::fs::write(&path, serde_json::to_vec(self)?).into_error_result_with(|| path.to_string_lossy())?;
+
+		Ok(())
+	}
+
+	/// Builds the graph of a finished build, hashing every dependency as it is now.
+	pub fn new(project: u64, outputs: BTreeMap<RelativePathBuf, OutputDeps>, hashes: &mut Hashes) -> Result<Self, Error> {
+		let mut graph = Self { project, outputs, ..Default::default() };
+
+		for deps in graph.outputs.values() {
+			for path in &deps.files {
+				graph.files.insert(path.clone(), hashes.file(path));
+			}
+
+			for path in &deps.globals {
+				graph.globals.insert(path.clone(), hashes.global(path)?);
+			}
+		}
+
+		Ok(graph)
+	}
+
+	/// Returns the recorded dependencies of `output` if none of them changed since.
+	pub fn fresh(&self, output: &RelativePathBuf, file: u64, hashes: &Hashes) -> Option<&OutputDeps> {
+		let deps = self.outputs.get(output)?;
+
+		let files_fresh = deps.files.iter().all(|p| self.files.get(p).is_some_and(|h| hashes.files.get(p) == Some(h)));
+		let globals_fresh = deps.globals.iter().all(|p| self.globals.get(p).is_some_and(|h| hashes.globals.get(p) == Some(h)));
+
+		(deps.file == file && files_fresh && globals_fresh).then_some(deps)
+	}
+}
+
+/// Current hashes of files and globals, computed once per build.
+pub struct Hashes<'a> {
+	globals_object: &'a liquid::Object,
+	files: BTreeMap<PathBuf, u64>,
+	globals: BTreeMap<String, u64>,
+}
+
+impl<'a> Hashes<'a> {
+	/// Hashes everything the previous graph depended on, so [`DepGraph::fresh`] can be checked from any thread.
+	/// If the `changed` files are known, the others keep the hashes they had.
+	pub fn new(globals_object: &'a liquid::Object, previous: Option<&DepGraph>, changed: Option<&HashSet<PathBuf>>) -> Result<Self, Error> {
+		let mut hashes = Self { globals_object, files: BTreeMap::new(), globals: BTreeMap::new() };
+
+		if let Some(previous) = previous {
+			for (path, hash) in &previous.files {
+				match changed {
+					Some(changed) if !changed.contains(path) => { hashes.files.insert(path.clone(), *hash); },
+					_ => { hashes.file(path); },
+				}
+			}
+
+			for path in previous.globals.keys() {
+				hashes.global(path)?;
+			}
+		}
+
+		Ok(hashes)
+	}
+
+	/// Files that don't exist hash to 0.
+	pub fn file(&mut self, path: &Path) -> u64 {
+		*self.files.entry(path.to_owned())
+			.or_insert_with(|| std::fs::read(path).map_or(0, |bytes| hash(&bytes)))
+	}
+
+	/// Globals that don't exist hash to 0.
+	pub fn global(&mut self, path: &str) -> Result<u64, Error> {
+		if let Some(hash) = self.globals.get(path) {
+			return Ok(*hash);
+		}
+
+		let mut value = Some(self.globals_object as &dyn ValueView);
+		for key in path.split('.').filter(|k| !k.is_empty()) {
+			value = value.and_then(|v| v.as_object()).and_then(|o| o.get(key));
+		}
+
+		// Going through `serde_json::Value` sorts the keys, where liquid's objects are unordered.
+		let hash = match value {
+			Some(value) => hash(&serde_json::to_vec(&serde_json::to_value(value.to_value())?)?),
+			None => 0,
+		};
+
+		self.globals.insert(path.to_owned(), hash);
+		Ok(hash)
+	}
+}
+
+pub fn hash(bytes: &[u8]) -> u64 {
+	xxhash_rust::xxh3::xxh3_64(bytes)
+}
+
+/// Hashes `jake.yml` and every file in the plugins directory.
+pub fn project_hash(config: &JakeConfig) -> Result<u64, Error> {
+	let mut bytes = Vec::from(env!("CARGO_PKG_VERSION").as_bytes());
+	bytes.extend(std::fs::read(config.project_dir.join(crate::config::CONFIG_FILE))?);
+
+	if config.plugins_dir.exists() {
+		let plugins = walkdir::WalkDir::new(&config.plugins_dir)
+			.sort_by_file_name()
+			.into_iter()
+			.filter_map(Result::ok)
+			.filter(|f| f.file_type().is_file());
+
+		for entry in plugins {
+			bytes.extend(entry.path().as_os_str().as_encoded_bytes());
+			bytes.extend(std::fs::read(entry.path())?);
+		}
+	}
+
+	Ok(hash(&bytes))
+}
+
+thread_local! {
+	static RECORDING: RefCell<Option<OutputDeps>> = const { RefCell::new(None) };
+}
+
+/// Runs `f`, recording the dependencies it touches on this thread.
+pub fn recording<T>(f: impl FnOnce() -> T) -> (T, OutputDeps) {
+	let outer = RECORDING.replace(Some(OutputDeps::default()));
+	let result = f();
+	let deps = RECORDING.replace(outer).unwrap_or_default();
+
+	(result, deps)
+}
+
+/// Notes a layout or partial as a dependency of the output being recorded, if any.
+pub fn record_file(path: impl Into<PathBuf>) {
+	RECORDING.with_borrow_mut(|deps| if let Some(deps) = deps {
+		deps.files.insert(path.into());
+	});
+}
+
+/// Notes a global as a dependency of the output being recorded, if any.
+pub fn record_global(path: impl Into<String>) {
+	RECORDING.with_borrow_mut(|deps| if let Some(deps) = deps {
+		deps.globals.insert(path.into());
+	});
+}
+
+/// A view of the globals that records which of them templates read.
+///
+/// Nested objects are tracked key by key, everything else as a whole.
+#[derive(Debug)]
+pub struct TrackedObject<'a> {
+	path: String,
+	object: &'a liquid::Object,
+	children: BTreeMap<&'a str, TrackedObject<'a>>,
+}
+
+impl<'a> TrackedObject<'a> {
+	pub fn new(object: &'a liquid::Object) -> Self {
+		Self::with_path(String::new(), object)
+	}
+
+	fn with_path(path: String, object: &'a liquid::Object) -> Self {
+		let children = object.iter()
+			.filter_map(|(key, value)| match value {
+				Value::Object(child) => Some((key.as_str(), Self::with_path(Self::join(&path, key), child))),
+				_ => None,
+			})
+			.collect();
+
+		Self { path, object, children }
+	}
+
+	fn join(path: &str, key: &str) -> String {
+		if path.is_empty() { key.to_owned() } else { format!("{path}.{key}") }
+	}
+
+	fn record(&self) {
+		record_global(self.path.as_str());
+	}
+}
+
+impl ValueView for TrackedObject<'_> {
+	fn as_debug(&self) -> &dyn std::fmt::Debug { self }
+
+	fn render(&self) -> DisplayCow<'_> {
+		self.record();
+		self.object.render()
+	}
+
+	fn source(&self) -> DisplayCow<'_> {
+		self.record();
+		self.object.source()
+	}
+
+	fn type_name(&self) -> &'static str {
+		self.object.type_name()
+	}
+
+	fn query_state(&self, state: State) -> bool {
+		self.record();
+		self.object.query_state(state)
+	}
+
+	fn to_kstr(&self) -> KStringCow<'_> {
+		self.record();
+		self.object.to_kstr()
+	}
+
+	fn to_value(&self) -> Value {
+		self.record();
+		self.object.to_value()
+	}
+
+	fn as_object(&self) -> Option<&dyn ObjectView> {
+		Some(self)
+	}
+}
+
+impl ObjectView for TrackedObject<'_> {
+	fn as_value(&self) -> &dyn ValueView { self }
+
+	fn size(&self) -> i64 {
+		self.record();
+		ObjectView::size(self.object)
+	}
+
+	fn keys<'k>(&'k self) -> Box<dyn Iterator<Item = KStringCow<'k>> + 'k> {
+		self.record();
+		ObjectView::keys(self.object)
+	}
+
+	fn values<'k>(&'k self) -> Box<dyn Iterator<Item = &'k dyn ValueView> + 'k> {
+		self.record();
+		ObjectView::values(self.object)
+	}
+
+	fn iter<'k>(&'k self) -> Box<dyn Iterator<Item = (KStringCow<'k>, &'k dyn ValueView)> + 'k> {
+		self.record();
+		ObjectView::iter(self.object)
+	}
+
+	fn contains_key(&self, index: &str) -> bool {
+		let contains = ObjectView::contains_key(self.object, index);
+
+		// A missing global matters too, in case it shows up later.
+		if !contains {
+			record_global(Self::join(&self.path, index));
+		}
+
+		contains
+	}
+
+	fn get<'s>(&'s self, index: &str) -> Option<&'s dyn ValueView> {
+		if let Some(child) = self.children.get(index) {
+			return Some(child);
+		}
+
+		record_global(Self::join(&self.path, index));
+		ObjectView::get(self.object, index)
+	}
+}
+
+#[cfg(test)]
+mod tests {
+	use super::*;
+
+	fn temp_dir(name: &str) -> PathBuf {
+		let dir = std::env::temp_dir().join(format!("jake-incremental-{name}-{}", std::process::id()));
+		std::fs::create_dir_all(&dir).expect("Temp dir is created");
+		dir
+	}
+
+	fn deps(files: &[&Path], globals: &[&str], file: u64) -> OutputDeps {
+		OutputDeps {
+			file,
+			files: files.iter().map(|&path| path.to_owned()).collect(),
+			globals: globals.iter().map(|&path| path.to_owned()).collect(),
+		}
+	}
+
+	#[test]
+	fn freshness() {
+		let dir = temp_dir("fresh");
+		let (layout, partial) = (dir.join("layout.html"), dir.join("partial.html"));
+		std::fs::write(&layout, "layout").expect("Layout is written");
+		std::fs::write(&partial, "partial").expect("Partial is written");
+
+		let globals = liquid::object!({ "site": { "title": "Site", "data": { "a": 1, "b": 2 } } });
+		let (a, b) = (RelativePathBuf::from("a.html"), RelativePathBuf::from("b.html"));
+		let outputs = BTreeMap::from([
+			(a.clone(), deps(&[ &layout ], &[ "site.title" ], 1)),
+			(b.clone(), deps(&[ &partial ], &[ "site.data.b" ], 2)),
+		]);
+
+		let mut hashes = Hashes::new(&globals, None, None).expect("Hashes");
+		let graph = DepGraph::new(0, outputs, &mut hashes).expect("Graph builds");
+
+		let fresh = |globals: &liquid::Object, changed: Option<&HashSet<PathBuf>>| {
+			let hashes = Hashes::new(globals, Some(&graph), changed).expect("Hashes");
+			[ (&a, 1), (&b, 2) ].map(|(output, file)| graph.fresh(output, file, &hashes).is_some())
+		};
+
+		let unchanged = fresh(&globals, None);
+		let edited_file = graph.fresh(&a, 3, &Hashes::new(&globals, Some(&graph), None).expect("Hashes")).is_some();
+
+		std::fs::write(&layout, "edited layout").expect("Layout is edited");
+		let edited_layout = fresh(&globals, None);
+		// Files known not to have changed keep their old hashes.
+		let untold = fresh(&globals, Some(&HashSet::new()));
+		let told = fresh(&globals, Some(&HashSet::from([ partial.clone() ])));
+		std::fs::write(&layout, "layout").expect("Layout is restored");
+
+		std::fs::write(&partial, "edited partial").expect("Partial is edited");
+		let edited_partial = fresh(&globals, None);
+		std::fs::write(&partial, "partial").expect("Partial is restored");
+
+		let unread_global = fresh(&liquid::object!({ "site": { "title": "Site", "data": { "a": 10, "b": 2 } } }), None);
+		let read_global = fresh(&liquid::object!({ "site": { "title": "Site", "data": { "a": 1, "b": 20 } } }), None);
+
+		let _ = std::fs::remove_dir_all(&dir);
+
+		assert_eq!(unchanged, [ true, true ]);
+		assert!(!edited_file);
+		assert_eq!(edited_layout, [ false, true ]);
+		assert_eq!(untold, [ true, true ]);
+		assert_eq!(told, [ true, true ]);
+		assert_eq!(edited_partial, [ true, false ]);
+		assert_eq!(unread_global, [ true, true ]);
+		assert_eq!(read_global, [ true, false ]);
+	}
+
+	#[test]
+	fn tracked_globals() {
+		let globals = liquid::object!({ "site": { "title": "Site", "data": { "authors": [ "a", "b" ], "tags": {} } }, "page": { "title": "Page" } });
+		let tracked = TrackedObject::new(&globals);
+
+		let template = liquid::ParserBuilder::with_stdlib().build().expect("Parser builds")
+			.parse("{{ site.title }} {{ site.data.authors | size }} {% if site.missing %}{% endif %}{{ page }}")
+			.expect("Template parses");
+
+		let (output, deps) = recording(|| template.render(&tracked));
+		output.expect("Template renders");
+
+		assert_eq!(deps.globals, [ "page", "site.data.authors", "site.missing", "site.title" ].map(String::from).into_iter().collect::<BTreeSet<_>>());
+		assert!(deps.files.is_empty());
+	}
+
+	#[test]
+	fn project_changes() {
+		let dir = temp_dir("project");
+		let config = JakeConfig::test(&dir);
+		std::fs::create_dir_all(&config.plugins_dir).expect("Plugins dir is created");
+		std::fs::write(dir.join(crate::config::CONFIG_FILE), "base_url: /a").expect("Config is written");
+		std::fs::write(config.plugins_dir.join("init.lua"), "-- a").expect("Plugin is written");
+
+		let hash = || project_hash(&config).expect("Project hashes");
+
+		let first = hash();
+		let same = hash();
+		std::fs::write(config.plugins_dir.join("init.lua"), "-- b").expect("Plugin is edited");
+		let edited_plugin = hash();
+		std::fs::write(config.plugins_dir.join("more.lua"), "").expect("Plugin is added");
+		let added_plugin = hash();
+		std::fs::write(dir.join(crate::config::CONFIG_FILE), "base_url: /b").expect("Config is edited");
+		let edited_config = hash();
+
+		let _ = std::fs::remove_dir_all(&dir);
+
+		assert_eq!(first, same);
+		assert_ne!(first, edited_plugin);
+		assert_ne!(edited_plugin, added_plugin);
+		assert_ne!(added_plugin, edited_config);
+	}
+}
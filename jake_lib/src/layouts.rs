@@ -0,0 +1,171 @@
+use crate::{converters::{Converter, Converters}, error::{Error, JakeError::*, ResultExtensions}, frontmatter::{self, FrontMatter}, JakeConfig};
+use kstring::KString;
+use relative_path::{RelativePath, RelativePathBuf};
+use std::collections::BTreeMap;
+
+/// The front matter key naming the layout a page or layout is wrapped in.
+pub const LAYOUT_KEY: &str = "layout";
+
+pub struct JakeLayout {
+	pub path: RelativePathBuf,
+	pub frontmatter: Option<FrontMatter>,
+	pub template: liquid::Template,
+	/// Converts the layout once rendered, the same as a page with its extension.
+	/// The content it wraps isn't converted along with it.
+	pub converter: Option<Converter>,
+}
+
+/// Every layout of the site, by path relative to the layout directory.
+pub struct Layouts(BTreeMap<RelativePathBuf, JakeLayout>);
+
+impl Layouts {
+	pub fn collect(config: &JakeConfig, parser: &liquid::Parser, converters: &Converters) -> Result<Self, Error> {
+		let JakeConfig { layout_dir, .. } = config;
+
+		let mut layouts = BTreeMap::new();
+
+		for entry in crate::walk_files(layout_dir) {
+			let (entry, rel_path) = entry?;
+
+			let (frontmatter, content) = frontmatter::file_frontmatter_content(entry.path())
+				.into_error_result_with(|| rel_path.as_str())?
+				.ok_or(FileNotUtf8(rel_path.clone()))?;
+
+			let template = parser.parse(&content)
+				.into_error_result_with(|| rel_path.as_str())?;
+
+			let converter = rel_path.extension().and_then(|ext| converters.get(ext)).cloned();
+
+			layouts.insert(rel_path.clone(), JakeLayout { path: rel_path, frontmatter, template, converter });
+		}
+
+		Ok(Self(layouts))
+	}
+
+	/// Finds a layout by its path, with or without its extension.
+	/// Failing that, a name without a directory matches any layout with that file stem.
+	pub fn get(&self, name: &str) -> Result<&JakeLayout, Error> {
+		let path = RelativePath::new(name.trim_start_matches('/')).normalize();
+
+		if let Some(layout) = self.0.get(&path) {
+			return Ok(layout);
+		}
+
+		if let Some(layout) = self.unique(name, |p| p.with_extension("") == path)? {
+			return Ok(layout);
+		}
+
+		if !name.contains('/') && let Some(layout) = self.unique(name, |p| p.file_stem() == Some(name))? {
+			return Ok(layout);
+		}
+
+		Err(LayoutNotFound(KString::from_ref(name)).into())
+	}
+
+	fn unique(&self, name: &str, matches: impl Fn(&RelativePath) -> bool) -> Result<Option<&JakeLayout>, Error> {
+		let mut found = self.0.values().filter(|layout| matches(&layout.path));
+
+		match (found.next(), found.next()) {
+			(Some(first), Some(second)) => {
+				let paths = [ first, second ].into_iter().chain(found).map(|l| l.path.clone()).collect();
+				Err(AmbiguousLayout(KString::from_ref(name), paths).into())
+			},
+			(layout, _) => Ok(layout),
+		}
+	}
+
+	/// The layouts a page using `name` is wrapped in, innermost first.
+	pub fn chain(&self, name: &str) -> Result<Vec<&JakeLayout>, Error> {
+		let mut chain: Vec<&JakeLayout> = Vec::new();
+		let mut name = Some(KString::from_ref(name));
+
+		while let Some(next) = name {
+			let layout = self.get(&next)?;
+
+			if chain.iter().any(|l| l.path == layout.path) {
+				let cycle = chain.iter().map(|l| l.path.clone()).chain([ layout.path.clone() ]).collect();
+				return Err(LayoutCycle(cycle).into());
+			}
+
+			name = layout.frontmatter.as_ref()
+				.and_then(|fm| fm.get(LAYOUT_KEY))
+				.and_then(|l| l.as_str())
+				.map(KString::from_ref);
+
+			chain.push(layout);
+		}
+
+		Ok(chain)
+	}
+}
+
+/// The `layout` object templates see while a layout renders: its front matter,
+/// over the front matter of the layouts it is wrapped in.
+pub fn merged_frontmatter(chain: &[&JakeLayout]) -> Result<liquid::Object, Error> {
+	let mut merged = liquid::Object::new();
+
+	for layout in chain.iter().rev() {
+		if let Some(frontmatter) = &layout.frontmatter {
+			merged.extend(liquid::to_object(frontmatter)?);
+		}
+	}
+
+	Ok(merged)
+}
+
+#[cfg(test)]
+mod tests {
+	use super::*;
+
+	fn layouts(layouts: &[(&str, Option<&str>)]) -> Layouts {
+		let parser = liquid::ParserBuilder::with_stdlib().build().expect("Stdlib parser builds");
+
+		Layouts(layouts.iter().map(|&(path, parent)| {
+			let frontmatter = parent.map(|parent| FrontMatter::from([ (LAYOUT_KEY.into(), parent.into()) ]));
+			let template = parser.parse("{{ content }}").expect("Template parses");
+
+			(path.into(), JakeLayout { path: path.into(), frontmatter, template, converter: None })
+		}).collect())
+	}
+
+	fn chain<'a>(layouts: &'a Layouts, name: &str) -> Result<Vec<&'a str>, Error> {
+		Ok(layouts.chain(name)?.into_iter().map(|l| l.path.as_str()).collect())
+	}
+
+	#[test]
+	fn by_path() {
+		let layouts = layouts(&[ ("base.html", None), ("blog/post.md", None), ("docs/post.html", None), ("page.liquid", None) ]);
+		let path = |name| layouts.get(name).map(|l| l.path.as_str());
+
+		assert_eq!(path("base.html").ok(), Some("base.html"));
+		assert_eq!(path("/base.html").ok(), Some("base.html"));
+		assert_eq!(path("base").ok(), Some("base.html"));
+		assert_eq!(path("page").ok(), Some("page.liquid"));
+		assert_eq!(path("blog/post").ok(), Some("blog/post.md"));
+		assert_eq!(path("blog/../docs/post").ok(), Some("docs/post.html"));
+
+		assert!(matches!(path("post"), Err(Error::JakeError(AmbiguousLayout(..)))));
+		assert!(matches!(path("missing"), Err(Error::JakeError(LayoutNotFound(..)))));
+		// Stems only stand in for names without a directory.
+		assert!(matches!(path("blog/base"), Err(Error::JakeError(LayoutNotFound(..)))));
+	}
+
+	#[test]
+	fn chains() {
+		let layouts = layouts(&[ ("base.html", None), ("blog/post.html", Some("base")), ("blog/series.html", Some("blog/post.html")) ]);
+
+		assert_eq!(chain(&layouts, "base").ok(), Some(vec![ "base.html" ]));
+		assert_eq!(chain(&layouts, "series").ok(), Some(vec![ "blog/series.html", "blog/post.html", "base.html" ]));
+	}
+
+	#[test]
+	fn cycles() {
+		let layouts = layouts(&[ ("a.html", Some("b")), ("b.html", Some("c.html")), ("c.html", Some("a")), ("self.html", Some("self")) ]);
+
+		let result = chain(&layouts, "a");
+		assert!(matches!(&result, Err(Error::JakeError(LayoutCycle(cycle)))
+			if cycle.iter().map(|p| p.as_str()).eq([ "a.html", "b.html", "c.html", "a.html" ])), "Expected a cycle, got {result:?}");
+
+		assert!(matches!(chain(&layouts, "self"), Err(Error::JakeError(LayoutCycle(cycle))) if cycle.len() == 2));
+	}
+}
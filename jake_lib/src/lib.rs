@@ -22,133 +22,607 @@
 
 pub mod error;
 
-pub use data_strctures::JakeConfig;
+pub use config::{JakeConfig, WatchFilter, CONFIG_FILE};
 
 mod lua;
+mod config;
+mod collections;
+mod converters;
+mod data;
+mod layouts;
+mod pagination;
+mod partials;
+mod permalinks;
+mod outputs;
+mod incremental;
 mod frontmatter;
 mod liquid_extensions;
 pub(crate) mod data_strctures;
 
+use converters::Converter;
 use error::{Error, JakeError::*, ResultExtensions};
 use frontmatter::FrontMatter;
-use data_strctures::{FileContent, FileSource, JakeFileT1, JakeFileT3};
-use kstring::KString;
+use data_strctures::{FileContent, FileSource, JakeFileT1, JakeFileT2, JakeFileT3};
 use liquid::ValueView;
-use liquid_core::{runtime, Renderable, Runtime};
+use liquid_core::{runtime, Renderable};
+use rayon::prelude::*;
 use relative_path::{RelativePath, RelativePathBuf};
-use std::collections::HashMap;
+use std::{collections::{HashMap, HashSet}, path::{Path, PathBuf}, sync::{Arc, Mutex}};
+
+/// How a build goes about its work.
+#[derive(Debug, Clone, Default)]
+pub struct BuildOptions {
+	/// Only render outputs whose dependencies changed since the last build.
+	pub incremental: bool,
+	/// Write pages marked as drafts too.
+	pub drafts: bool,
+}
 
-pub fn process_project(config: &JakeConfig) -> Result<(), Error> {
-	let lua = unsafe { mlua::Lua::unsafe_new() };
-	let lua::LuaResult { tags, converters, filters, mut files, post_processors } = lua::setup_lua_state(&lua, config, collect_src(config)?)?;
+/// What the last build did.
+#[derive(Debug, Clone, Copy, Default)]
+pub struct BuildStats {
+	pub rendered: usize,
+	/// Outputs the last build left up to date.
+	pub skipped: usize,
+	/// Binary files that were already copied.
+	pub skipped_binaries: usize,
+	/// Outputs no source makes anymore.
+	pub removed: usize,
+}
 
-	let mut liquid_builder = liquid::ParserBuilder::with_stdlib();
+pub fn process_project(config: &JakeConfig, options: &BuildOptions) -> Result<(), Error> {
+	Site::new(config.clone(), options.clone())?.build()
+}
 
-	liquid_builder = liquid_builder.block(lua::liquid_api::block::LuaBlock { lua: lua.clone() })
-		.filter(liquid_extensions::Jsonify)
-		.filter(liquid_extensions::Render);
+/// A loaded project, kept around between builds so a rebuild only redoes what changed.
+pub struct Site {
+	config: JakeConfig,
+	options: BuildOptions,
+	lua: mlua::Lua,
+	tags: Vec<(String, lua::liquid_api::tag::TagFunc)>,
+	blocks: Vec<(String, lua::liquid_api::tag::TagFunc)>,
+	filters: Vec<(String, mlua::Function)>,
+	post_processors: Option<mlua::Function>,
+	converters: converters::Converters,
+	globals: FrontMatter,
+	data: serde_json::Map<String, serde_json::Value>,
+	parser: liquid::Parser,
+	layouts: layouts::Layouts,
+	/// The source files as read, before Lua got to them.
+	sources: HashMap<RelativePathBuf, JakeFileT1>,
+	/// Every file as Lua left it.
+	files: Vec<JakeFileT2>,
+	project_hash: u64,
+	graph: Option<incremental::DepGraph>,
+	stats: BuildStats,
+	memory: Mutex<Memory>,
+}
 
-	for (tag, func) in tags {
-		liquid_builder = liquid_builder.tag(lua::liquid_api::tag::LuaTag { tag, func, lua: lua.clone() });
+impl Site {
+	/// Reads the project and runs its plugins.
+	pub fn new(config: JakeConfig, options: BuildOptions) -> Result<Self, Error> {
+		let lua = unsafe { mlua::Lua::unsafe_new() };
+		let sources = collect_src(&config)?;
+		let lua::LuaResult { tags, blocks, converters, filters, files, post_processors, globals, data } =
+			lua::setup_lua_state(&lua, &config, sources.clone(), data::collect_data(&config)?)?;
+
+		let mut converter_map = converters::Converters::with_builtins(&config);
+		for (ext, converter) in converters {
+			match converter {
+				Some(converter) => converter_map.register(ext, converter),
+				None => converter_map.remove(&ext),
+			}
+		}
+
+		let parser = build_parser(&config, &lua, &tags, &blocks, &filters)?;
+		let layouts = layouts::Layouts::collect(&config, &parser, &converter_map)?;
+
+		let graph = options.incremental
+			.then(|| incremental::DepGraph::load(&config))
+			.flatten();
+
+		Ok(Self {
+			project_hash: incremental::project_hash(&config)?,
+			sources: sources.into_iter().map(|f| (f.source.clone(), f)).collect(),
+			converters: converter_map,
+			config,
+			options,
+			lua,
+			tags,
+			blocks,
+			filters,
+			post_processors,
+			globals,
+			data,
+			parser,
+			layouts,
+			files,
+			graph,
+			stats: BuildStats::default(),
+			memory: Mutex::default(),
+		})
 	}
 
-	for (filter, func) in filters {
-		liquid_builder = liquid_builder.filter(lua::liquid_api::filter::Lua { filter, func, lua: lua.clone() });
+	/// Renders the site. Incremental builds skip outputs the last build left up to date.
+	pub fn build(&mut self) -> Result<(), Error> {
+		self.render(None)
 	}
 
-	files.retain(|f| f.to_write);
+	/// Catches up with changes to the given paths, then renders the outputs they affect.
+	///
+	/// Changes to plugins or data files, new source files, and edits to front matter run the plugins again from scratch.
+	/// Other edits to sources, and to layouts and includes, keep what the plugins made of the site last time,
+	/// so files made in Lua stay as they were.
+	pub fn rebuild(&mut self, changed: &[PathBuf]) -> Result<(), Error> {
+		let changed: HashSet<_> = changed.iter().cloned().collect();
 
-	let liquid_parser = liquid_builder.build()?;
+		self.catch_up(&changed)?;
+		self.render(Some(&changed))
+	}
 
-	let files: Vec<_> = files.into_iter().map(|f| Ok::<_, Error>(JakeFileT3 {
-		source: f.source,
-		output: f.output,
-		front_matter: f.front_matter,
-		template: if let FileContent::Utf8(content) = f.content {
-			FileContent::Utf8(liquid_parser.parse(&content)?)
-		} else { FileContent::Binary },
-		to_write: f.to_write,
-		post_processor: f.post_processor,
-	})).try_collect()?;
+	/// Catches up with changes to the given paths like [`Site::rebuild`], without rendering anything.
+	/// Outputs rendered in memory are dropped.
+	pub fn update(&mut self, changed: &[PathBuf]) -> Result<(), Error> {
+		self.catch_up(&changed.iter().cloned().collect())?;
+		*self.memory.get_mut().map_err(|_| Misc("Poisoned lock"))? = Memory::default();
 
-	let layouts = collect_layouts(config, &liquid_parser)?;
+		Ok(())
+	}
 
-	let liquid_site_scope: liquid::Object = serde_yaml::from_str(&std::fs::read_to_string(config.project_dir.join("jake.yml"))?)?;
+	/// Renders a single output in memory, leaving the output directory alone.
+	///
+	/// Outputs render the first time they're asked for, and are kept until [`Site::update`].
+	/// Returns `Ok(None)` if nothing makes `output`.
+	/// The site-wide post-processor doesn't run, as it works on the output directory.
+	pub fn output(&self, output: &RelativePath) -> Result<Option<Arc<[u8]>>, Error> {
+		let output = output.normalize();
 
-	let liquid_runtime = liquid_core::runtime::RuntimeBuilder::new()
-		// .set_partials(values)
-		.set_globals(&liquid_site_scope)
-		.build();
+		if let Some(content) = self.memory.lock().map_err(|_| Misc("Poisoned lock"))?.outputs.get(&output) {
+			return Ok(Some(content.clone()));
+		}
 
-	// let liquid_lua_scope = lua::liquid_api::liquid_view::LuaValueView::new(lua.globals(), &lua)?;
-	// let liquid_lua_scope = liquid_core::runtime::StackFrame::new(liquid_runtime, liquid_lua_scope);
+		let plan = self.memory_plan()?;
 
-	let mut skipped = 0u32;
+		let Some(&(index, page)) = plan.index.get(&output) else {
+			return Ok(None);
+		};
 
-	for file in files {
-		if !file.to_write { continue; }
-		
-		match file.template {
+		let file = &plan.files[index];
+		let content: Arc<[u8]> = match &file.template {
 			FileContent::Utf8(template) => {
-				// let scope = [ liquid_site_scope.to_owned(), liquid::to_object(&file.front_matter)? ].into_iter().flatten().collect();
-				let data = liquid::to_object(&file.front_matter)?;
-				let scope = liquid_core::runtime::StackFrame::new(&liquid_runtime, &data);
-				let content = parse_content(&layouts, &template, file.source, &scope, &lua, &file.post_processor)?;
+				let (output, paginator) = &plan.renders[index][page];
+				self.render_output(file, template, output, paginator.clone(), &plan.globals, plan.partials.as_ref())?.into_bytes().into()
+			},
+			FileContent::Binary => {
+				let source = binary_source(&self.config, file)?;
+				std::fs::read(&source).into_error_result_with(|| source.to_string_lossy())?.into()
+			},
+		};
+
+		self.memory.lock().map_err(|_| Misc("Poisoned lock"))?.outputs.insert(output, content.clone());
+
+		Ok(Some(content))
+	}
+
+	/// What the last build did.
+	pub fn stats(&self) -> BuildStats {
+		self.stats
+	}
+
+	/// How many outputs were rendered in memory since the last change.
+	pub fn rendered_in_memory(&self) -> usize {
+		self.memory.lock().map_or(0, |memory| memory.outputs.len())
+	}
+
+	/// Whether anything makes `output`, without rendering it.
+	pub fn has_output(&self, output: &RelativePath) -> Result<bool, Error> {
+		Ok(self.memory_plan()?.index.contains_key(&output.normalize()))
+	}
+
+	/// The plan outputs render from in memory, worked out the first time it's needed.
+	fn memory_plan(&self) -> Result<Arc<Plan>, Error> {
+		let mut memory = self.memory.lock().map_err(|_| Misc("Poisoned lock"))?;
+
+		Ok(match &memory.plan {
+			Some(plan) => plan.clone(),
+			None => memory.plan.insert(Arc::new(self.plan()?)).clone(),
+		})
+	}
+
+	fn catch_up(&mut self, changed: &HashSet<PathBuf>) -> Result<(), Error> {
+		let under = |dir: &Path| changed.iter().any(|p| p.starts_with(dir));
+		let JakeConfig { plugins_dir, data_dir, source_dir, layout_dir, includes_dir, .. } = &self.config;
+		let (plugins, sources, layouts, includes) = (under(plugins_dir) || under(data_dir), under(source_dir), under(layout_dir), under(includes_dir));
+
+		if plugins || (sources && !self.update_sources()?) {
+			*self = Self::new(self.config.clone(), self.options.clone())?;
+		} else {
+			if includes {
+				self.parser = build_parser(&self.config, &self.lua, &self.tags, &self.blocks, &self.filters)?;
+			}
+
+			if includes || layouts {
+				self.layouts = layouts::Layouts::collect(&self.config, &self.parser, &self.converters)?;
+			}
+		}
+
+		Ok(())
+	}
+
+	/// Reads the source files again, keeping what Lua made of the edited ones last time.
+	///
+	/// Only edits to content are caught up with: an edited file keeps the output, front matter and post-processors
+	/// Lua gave it before, even ones Lua worked out from its content.
+	/// Returns `false` if that isn't enough, and the plugins have to run again:
+	/// for new files, edited front matter, or content Lua changed.
+	fn update_sources(&mut self) -> Result<bool, Error> {
+		let sources: HashMap<_, _> = collect_src(&self.config)?.into_iter().map(|f| (f.source.clone(), f)).collect();
+
+		// New files have never been through Lua.
+		if sources.keys().any(|source| !self.sources.contains_key(source)) {
+			return Ok(false);
+		}
+
+		let mut files = Vec::with_capacity(self.files.len());
+
+		for file in &self.files {
+			let Some(source) = file.source.as_option() else {
+				files.push(file.clone());
+				continue;
+			};
 
-				let output = file.output.to_logical_path(&config.output_dir);
-				std::fs::create_dir_all(output.parent().ok_or_else(|| UnexpectedFilePath(output.clone()))?)?;
+			// Files of deleted sources go with them.
+			let (Some(old), Some(new)) = (self.sources.get(source), sources.get(source)) else {
+				continue;
+			};
 
-				std::fs::write(output, content)?;
+			if old == new {
+				files.push(file.clone());
+				continue;
+			}
+
+			// What Lua did shows against the file as it would have come out of Lua untouched.
+			let before = self.untouched(old.clone())?;
+			let after = self.untouched(new.clone())?;
+
+			// Lua may have worked anything out from the front matter, down to whether the file is written at all,
+			// and what it did to the content can't be redone on the new content.
+			if after.front_matter != before.front_matter || before.content != file.content {
+				return Ok(false);
+			}
+
+			files.push(JakeFileT2 { content: after.content, ..file.clone() });
+		}
+
+		self.files = files;
+		self.sources = sources;
+
+		Ok(true)
+	}
+
+	fn untouched(&self, file: JakeFileT1) -> Result<JakeFileT2, Error> {
+		Ok(lua::general_api::file::FileUserData::from_file(file, &self.lua)?.into_file(&self.lua)?)
+	}
+
+	/// Works out everything about the site short of rendering it.
+	fn plan(&self) -> Result<Plan, Error> {
+		let Self { ref config, ref parser, converters: ref converter_map, ref globals, ref data, .. } = *self;
+
+		let files: Vec<_> = self.files.iter().filter(|f| f.to_write && (self.options.drafts || !collections::is_draft(&f.front_matter))).cloned().map(|mut f| {
+			let is_default_output = f.source.as_option() == Some(&f.output);
+
+			// Files made in Lua have no source, so their output picks the converter instead.
+			let converter = (!f.content.is_binary())
+				.then(|| f.source.as_option().unwrap_or(&f.output))
+				.and_then(|s| s.extension())
+				.and_then(|ext| converter_map.get(ext).map(|c| (ext.to_owned(), c)));
+
+			// Converters only rename outputs Lua left with their source extension.
+			if let Some((ext, Converter { ext: Some(new_ext), .. })) = &converter && f.output.extension() == Some(ext.as_str()) {
+				f.output.set_extension(new_ext.as_str());
+			}
+
+			let context = f.source.as_option().unwrap_or(&f.output).to_string();
+			if let Some(output) = permalinks::resolve(config, &f.source, &f.output, &f.front_matter, is_default_output).into_error_result_with(|| context.as_str())? {
+				f.output = output;
+			}
+
+			let hash = incremental::hash(&serde_json::to_vec(&f)?);
+			let converter = converter.map(|(_, c)| c.clone());
+			let page = collections::page_object(config, &f.source, &f.output, &f.front_matter, f.content.as_option().map(String::as_str), converter.as_ref())?;
+
+			Ok::<_, Error>(JakeFileT3 {
+				collection: collections::collection_of(config, &f.source, &f.front_matter).cloned(),
+				hash,
+				page,
+				converter,
+				source: f.source,
+				output: f.output,
+				front_matter: f.front_matter,
+				template: if let FileContent::Utf8(content) = f.content {
+					FileContent::Utf8(parser.parse(&content).into_error_result_with(|| context.as_str())?)
+				} else { FileContent::Binary },
+				to_write: f.to_write,
+				post_processor: f.post_processor,
+			})
+		}).try_collect()?;
+
+		let mut liquid_site_scope = liquid::to_object(globals)?;
+		liquid_site_scope.insert("base_url".into(), liquid::model::Value::scalar(config.base_url.clone()));
+
+		let site_pages = collections::site_pages(config, &files);
+
+		// Data files and page listings live under `site`, next to whatever `site` holds in `jake.yml`.
+		match liquid_site_scope.entry("site").or_insert_with(|| liquid::model::Value::Object(Default::default())) {
+			liquid::model::Value::Object(site) => {
+				site.insert("data".into(), liquid::model::to_value(data)?);
+				site.extend(site_pages.clone());
 			},
-			FileContent::Binary => {
-				let output = file.output.to_logical_path(&config.output_dir);
-				std::fs::create_dir_all(output.parent().ok_or_else(|| UnexpectedFilePath(output.clone()))?)?;
+			_ => Err(InvalidConfig("site", String::from("must be a mapping to hold the data files and pages")))?,
+		}
+
+		// Partials are compiled by the parser, so they share its Lua tags and filters.
+		let liquid_partials = TemplateMirror::of(&parser.parse("")?).partials.clone()
+			.ok_or(Misc("Parser was built without partials"))?;
+
+		// let liquid_lua_scope = lua::liquid_api::liquid_view::LuaValueView::new(lua.globals(), &lua)?;
+		// let liquid_lua_scope = liquid_core::runtime::StackFrame::new(liquid_runtime, liquid_lua_scope);
 
-				const MSG: &str = "Only files with a src can be binary";
-				let source = file.source.into_option().expect(MSG).to_logical_path(&config.source_dir);
+		// Paginated pages render once per page of items, everything else just once.
+		let renders: Vec<Vec<_>> = files.iter().map(|file| {
+			let context = || file.source.as_option().unwrap_or(&file.output).to_string();
+
+			let pages = match file.template {
+				FileContent::Utf8(_) => pagination::paginate(config, &file.output, &file.front_matter, &site_pages).into_error_result_with(context)?,
+				FileContent::Binary => None,
+			};
+
+			Ok::<_, Error>(match pages {
+				Some(pages) => pages.into_iter().map(|(output, paginator)| (output, Some(paginator))).collect(),
+				None => vec![(file.output.clone(), None)],
+			})
+		}).try_collect()?;
+
+		outputs::check_outputs(files.iter().zip(&renders)
+			.flat_map(|(file, renders)| renders.iter().map(|(output, _)| (output.as_relative_path(), &file.source))))?;
+
+		let index = renders.iter().enumerate()
+			.flat_map(|(file, renders)| renders.iter().enumerate().map(move |(page, (output, _))| (output.normalize(), (file, page))))
+			.collect();
+
+		Ok(Plan { files, renders, index, globals: liquid_site_scope, partials: liquid_partials })
+	}
+
+	/// Renders every output, skipping those whose dependencies didn't change if there's a previous build to go by.
+	/// Of the files those depend on, only the `changed` ones are read again if given.
+	fn render(&mut self, changed: Option<&HashSet<PathBuf>>) -> Result<(), Error> {
+		let plan = self.plan()?;
+		let previous = self.graph.take().filter(|graph| graph.project == self.project_hash);
+
+		let config = &self.config;
+		std::fs::create_dir_all(&config.output_dir)?;
+
+		let mut hashes = incremental::Hashes::new(&plan.globals, previous.as_ref(), changed)?;
+		let tracked_globals = incremental::TrackedObject::new(&plan.globals);
+
+		// Files render in parallel. Lua calls still run one at a time, as mlua locks the state for each,
+		// and every file writes to its own output, so the result is the same as rendering them in order.
+		let results: Vec<Result<(u32, Vec<_>), Error>> = plan.files.par_iter().zip(&plan.renders).map(|(file, renders)| {
+			match &file.template {
+				FileContent::Utf8(template) => {
+					let mut skipped = 0;
+					let mut deps = Vec::with_capacity(renders.len());
+					let is_sass = matches!(file.converter, Some(Converter { kind: converters::ConverterKind::Sass(_), .. }));
+
+					for (output, paginator) in renders {
+						let output_path = output.to_logical_path(&config.output_dir);
+
+						// Sass imports aren't tracked, so Sass always compiles again.
+						if let Some(previous) = &previous && output_path.exists() && !is_sass
+							&& let Some(old_deps) = previous.fresh(output, file.hash, &hashes) {
+							skipped += 1;
+							deps.push((output.clone(), old_deps.clone()));
+							continue;
+						}
+
+						let (content, mut output_deps) = incremental::recording(|| {
+							self.render_output(file, template, output, paginator.clone(), &tracked_globals, plan.partials.as_ref())
+						});
+
+						std::fs::create_dir_all(output_path.parent().ok_or_else(|| UnexpectedFilePath(output_path.clone()))?)?;
+						std::fs::write(output_path, content?)?;
+
+						output_deps.file = file.hash;
+						deps.push((output.clone(), output_deps));
+					}
+
+					Ok((skipped, deps))
+				},
+				FileContent::Binary => {
+					let output = file.output.to_logical_path(&config.output_dir);
+					std::fs::create_dir_all(output.parent().ok_or_else(|| UnexpectedFilePath(output.clone()))?)?;
+
+					let source = binary_source(config, file)?;
+
+					if let (Ok(src), Ok(out)) = (source.metadata().and_then(|src| src.modified()), output.metadata().and_then(|src| src.modified())) && src < out {
+						return Ok((1, Vec::new()));
+					}
 
-				if let (Ok(src), Ok(out)) = (source.metadata().and_then(|src| src.modified()), output.metadata().and_then(|src| src.modified())) && src < out {
-					skipped += 1;
-				} else {
 					std::fs::copy(source, output)?;
 					// std::os::unix::fs::symlink(source, output)?; //? This is really really funny.
-				}
-			},
+
+					Ok((0, Vec::new()))
+				},
+			}
+		}).collect();
+
+		// Errors are reported in file order, whichever thread hit them first.
+		let mut stats = BuildStats::default();
+		let mut deps = std::collections::BTreeMap::new();
+		for (file, result) in plan.files.iter().zip(results) {
+			let (file_skipped, file_deps) = result?;
+
+			if file.template.is_binary() {
+				stats.skipped_binaries += file_skipped as usize;
+			} else {
+				stats.skipped += file_skipped as usize;
+				stats.rendered += file_deps.len() - file_skipped as usize;
+			}
+
+			deps.extend(file_deps);
+		}
+
+		if stats.skipped + stats.skipped_binaries > 0 {
+			eprintln!("Skipped {} up to date files", stats.skipped + stats.skipped_binaries);
+		}
+
+		let graph = incremental::DepGraph::new(self.project_hash, deps, &mut hashes)?;
+		graph.save(config)?;
+
+		let removed = outputs::remove_stale(config, plan.index.into_keys().collect())?;
+		if removed > 0 {
+			eprintln!("Removed {} stale outputs", removed);
+		}
+
+		stats.removed = removed;
+		self.stats = stats;
+		self.graph = Some(graph);
+
+		if let Some(post) = &self.post_processors {
+			post.call::<()>(())?;
+		}
+
+		Ok(())
+	}
+
+	/// Renders one output of a file.
+	fn render_output(
+		&self,
+		file: &JakeFileT3,
+		template: &liquid::Template,
+		output: &RelativePath,
+		paginator: Option<liquid::Object>,
+		globals: &dyn liquid::ObjectView,
+		partials: &dyn runtime::PartialStore,
+	) -> Result<String, Error> {
+		// Runtimes hold their own registers, so each render needs its own.
+		let liquid_runtime = liquid_core::runtime::RuntimeBuilder::new()
+			.set_partials(partials)
+			.set_globals(globals)
+			.build();
+
+		// let scope = [ liquid_site_scope.to_owned(), liquid::to_object(&file.front_matter)? ].into_iter().flatten().collect();
+		let mut data = liquid::to_object(&file.front_matter)?;
+		let mut page = file.page.clone();
+
+		if let Some(paginator) = paginator {
+			if let Some(listing) = pagination::listing(&file.front_matter) {
+				incremental::record_global(format!("site.{listing}"));
+			}
+
+			page.insert("url".into(), liquid::model::Value::scalar(collections::url_for(&self.config, output)));
+			data.insert("paginator".into(), liquid::model::Value::Object(paginator));
 		}
+
+		data.insert("page".into(), liquid::model::Value::Object(page));
+		// Assignments go over the page's own variables rather than under them, whether from `assign` or Lua,
+		// so they're seen for the rest of the page and its layouts.
+		let scope = liquid_core::runtime::GlobalFrame::new(liquid_core::runtime::StackFrame::new(&liquid_runtime, &data));
+		parse_content(&self.config, &self.layouts, template, file.source.clone(), &scope, &self.lua, file.converter.as_ref(), &file.post_processor)
 	}
+}
+
+/// Everything a build works out before rendering.
+struct Plan {
+	files: Vec<JakeFileT3>,
+	/// The outputs of each file, with their paginators if paginated.
+	renders: Vec<Vec<(RelativePathBuf, Option<liquid::Object>)>>,
+	/// Where each output is in `renders`, by normalized path.
+	index: HashMap<RelativePathBuf, (usize, usize)>,
+	globals: liquid::Object,
+	partials: Arc<dyn runtime::PartialStore + Send + Sync>,
+}
 
-	if skipped > 0 {
-		eprintln!("Skipped {} up to date files", skipped);
+/// What a site serving from memory has worked out and rendered so far.
+#[derive(Default)]
+struct Memory {
+	plan: Option<Arc<Plan>>,
+	outputs: HashMap<RelativePathBuf, Arc<[u8]>>,
+}
+
+fn build_parser(
+	config: &JakeConfig,
+	lua: &mlua::Lua,
+	tags: &[(String, lua::liquid_api::tag::TagFunc)],
+	blocks: &[(String, lua::liquid_api::tag::TagFunc)],
+	filters: &[(String, mlua::Function)],
+) -> Result<liquid::Parser, Error> {
+	let mut liquid_builder = liquid::ParserBuilder::with_stdlib()
+		.partials(partials::JakePartials::collect(config)?);
+
+	liquid_builder = liquid_builder.block(lua::liquid_api::block::LuaBlock { lua: lua.clone() })
+		.filter(liquid_extensions::Jsonify)
+		.filter(liquid_extensions::Render);
+
+	for (tag, func) in tags {
+		liquid_builder = liquid_builder.tag(lua::liquid_api::tag::LuaTag { tag: tag.clone(), func: func.clone(), lua: lua.clone() });
 	}
 
-	if let Some(post) = post_processors {
-		post.call::<()>(())?;
+	for (block, func) in blocks {
+		liquid_builder = liquid_builder.block(lua::liquid_api::block::LuaCustomBlock::new(block.clone(), func.clone(), lua.clone()));
 	}
 
-	Ok(())
+	for (filter, func) in filters {
+		liquid_builder = liquid_builder.filter(lua::liquid_api::filter::Lua { filter: filter.clone(), func: func.clone(), lua: lua.clone() });
+	}
+
+	Ok(liquid_builder.build()?)
+}
+
+/// Every file under `dir` with its path relative to `dir`, in file name order.
+/// Hidden entries, starting with `.`, are skipped along with everything in them.
+pub(crate) fn walk_files(dir: &Path) -> impl Iterator<Item = Result<(walkdir::DirEntry, RelativePathBuf), Error>> + '_ {
+	walkdir::WalkDir::new(dir)
+		.sort_by_file_name()
+		.into_iter()
+		.filter_entry(|e| !e.file_name().to_string_lossy().starts_with('.'))
+		.filter_map(Result::ok)
+		.filter(|f| f.file_type().is_file())
+		.map(move |entry| {
+			let rel_path = entry.path()
+				.strip_prefix(dir)
+				.ok()
+				.and_then(|p| RelativePathBuf::from_path(p).ok())
+				.ok_or_else(|| UnexpectedFilePath(entry.path().to_owned()))?;
+
+			Ok((entry, rel_path))
+		})
 }
 
 fn collect_src(config: &JakeConfig) -> Result<Vec<JakeFileT1>, Error> {
-	let JakeConfig { project_dir, source_dir, output_dir, plugins_dir, layout_dir, .. } = config;
+	let JakeConfig { project_dir, source_dir, plugins_dir, layout_dir, .. } = config;
 
-	std::fs::create_dir_all(output_dir)?;
 	std::fs::create_dir_all(plugins_dir)?;
 	std::fs::create_dir_all(layout_dir)?;
 
 	let mut files = Vec::with_capacity(16); // Better than starting at 0.
+
+	let filter = config.source_filter()?;
 	
 	let dir = walkdir::WalkDir::new(source_dir)
 		.into_iter()
-		.filter_entry(|e| !e.file_name().to_string_lossy().starts_with('.'))
+		.filter_entry(|e| !filter.is_generated(e.path()) && e.path().strip_prefix(source_dir).ok()
+			.and_then(|p| RelativePathBuf::from_path(p).ok())
+			.is_none_or(|p| !filter.is_ignored(&p)))
 		.filter_map(Result::ok);
 
-	const DEFAULT_FRONTMATTER_FILE: &str = ".jake.yml";
+	let defaults: Vec<_> = config.defaults.iter().map(|(glob, fm)| (glob.compile_matcher(), fm)).collect();
 
 	let mut frontmatter_glob: Vec<(globset::GlobMatcher, FrontMatter)> = Default::default();
 
 	for entry in dir {
 		if entry.file_type().is_dir() {
-			let conf_path = entry.path().join(DEFAULT_FRONTMATTER_FILE);
+			let conf_path = entry.path().join(config::DEFAULT_FRONTMATTER_FILE);
 			let get_rel_conf_path = || conf_path.strip_prefix(project_dir).expect("File not in proj directory").to_string_lossy();
 
 			if let Some(config) = conf_path.exists()
@@ -160,10 +634,7 @@ fn collect_src(config: &JakeConfig) -> Result<Vec<JakeFileT1>, Error> {
 
 				frontmatter_glob.extend_reserve(config.len());
 				for (glob, frontmatter) in config {
-					frontmatter_glob.push((globset::GlobBuilder::new(&format!("{}/{}", entry.path().to_string_lossy(), glob))
-						.backslash_escape(true)
-						.empty_alternates(true)
-						.build()
+					frontmatter_glob.push((config::build_glob(&format!("{}/{}", entry.path().to_string_lossy(), glob))
 						.into_error_result_with(get_rel_conf_path)?
 						.compile_matcher(), frontmatter));
 				}
@@ -172,6 +643,10 @@ fn collect_src(config: &JakeConfig) -> Result<Vec<JakeFileT1>, Error> {
 			continue;
 		}
 
+		if converters::is_sass_partial(entry.path()) {
+			continue;
+		}
+
 		let rel_path = entry.path()
 			.strip_prefix(source_dir)
 			.ok()
@@ -180,6 +655,12 @@ fn collect_src(config: &JakeConfig) -> Result<Vec<JakeFileT1>, Error> {
 
 		let mut front_matter = FrontMatter::default();
 
+		for (glob, fm) in &defaults {
+			if glob.is_match(rel_path.as_str()) {
+				front_matter.extend((*fm).clone());
+			}
+		}
+
 		for (glob, fm) in &frontmatter_glob {
 			if glob.is_match(entry.path()) {
 				front_matter.extend(fm.clone());
@@ -209,29 +690,99 @@ fn collect_src(config: &JakeConfig) -> Result<Vec<JakeFileT1>, Error> {
 	Ok(files)
 }
 
+/// Where a binary file is copied from, which only source files can be.
+fn binary_source(config: &JakeConfig, file: &JakeFileT3) -> Result<PathBuf, Error> {
+	let source = file.source.as_option().ok_or(Misc("Only files with a src can be binary"))?;
+	Ok(source.to_logical_path(&config.source_dir))
+}
+
+#[allow(clippy::too_many_arguments)]
 fn parse_content(
-	layouts: &HashMap<KString, JakeLayout>,
+	config: &JakeConfig,
+	layouts: &layouts::Layouts,
 	template: &liquid::Template,
 	source: FileSource<impl AsRef<RelativePath>>,
 	liquid_runtime: &dyn liquid_core::runtime::Runtime,
 	lua: &mlua::Lua,
+	converter: Option<&Converter>,
 	post_processor: &[mlua::Function],
 ) -> Result<String, Error> {
-	use lua::general_api::path::PathUserData;
-
 	let context = || source.as_option().map_or(String::from("Lua-generated File"), |p| p.as_ref().to_string());
 
-	pub struct TemplateMirror {
-		template: runtime::Template,
-		#[allow(dead_code)]
-		partials: Option<std::sync::Arc<dyn liquid_core::runtime::PartialStore + Send + Sync>>,
-	}
+	let chain = match liquid_runtime.try_get(&[ layouts::LAYOUT_KEY.into() ]) {
+		Some(layout) if !layout.is_nil() => layouts.chain(layout.to_kstr().as_str()).into_error_result_with(context)?,
+		_ => Vec::new(),
+	};
 
-	let mut content = unsafe { std::mem::transmute::<&liquid::Template, &TemplateMirror>(template) } // :T
-		.template.render(&liquid_runtime)
+	let path = source.as_option().map(|p| p.as_ref().to_logical_path(&config.source_dir));
+	let content = render_layer(template, &source, path.as_deref(), liquid_runtime, lua, converter, post_processor, chain.is_empty(), None)
 		.into_error_result_with(context)?;
 
-	let layout = liquid_runtime.try_get(&[ "layout".into() ]);
+	apply_layouts(config, &chain, content, liquid_runtime, lua, post_processor)
+		.into_error_result_with(context)
+}
+
+/// Wraps `content` in each layout of `chain` in turn, innermost first.
+///
+/// Each layout sees its own front matter over the scope it is wrapped around,
+/// and the front matter it inherits from the layouts around it as `layout`.
+fn apply_layouts(
+	config: &JakeConfig,
+	chain: &[&layouts::JakeLayout],
+	content: String,
+	liquid_runtime: &dyn liquid_core::runtime::Runtime,
+	lua: &mlua::Lua,
+	post_processor: &[mlua::Function],
+) -> Result<String, Error> {
+	let Some((layout, rest)) = chain.split_first() else {
+		return Ok(content);
+	};
+
+	incremental::record_file(layout.path.to_logical_path(&config.layout_dir));
+
+	let mut frontmatter = liquid::to_object(&layout.frontmatter)?;
+	frontmatter.insert(layouts::LAYOUT_KEY.into(), liquid::model::Value::Object(layouts::merged_frontmatter(chain)?));
+
+	let runtime = liquid_core::runtime::StackFrame::new(&liquid_runtime, frontmatter);
+
+	let path = layout.path.to_logical_path(&config.layout_dir);
+	let content = render_layer(&layout.template, &Some(&layout.path).into(), Some(&path), &runtime, lua, layout.converter.as_ref(), post_processor, rest.is_empty(), Some(content))
+		.into_error_result_with(|| layout.path.as_str())?;
+
+	apply_layouts(config, rest, content, &runtime, lua, post_processor)
+}
+
+/// Renders a page or layout on its own, then converts and post-processes it.
+///
+/// Layouts get the `content` they wrap, which goes in as is even if the layout is converted.
+/// A converted layout renders around a placeholder for it, swapped for the content after converting,
+/// so filters there such as `content | strip_html` or `content | size` act on the placeholder instead.
+#[allow(clippy::too_many_arguments)]
+fn render_layer(
+	template: &liquid::Template,
+	source: &FileSource<impl AsRef<RelativePath>>,
+	path: Option<&Path>,
+	liquid_runtime: &dyn liquid_core::runtime::Runtime,
+	lua: &mlua::Lua,
+	converter: Option<&Converter>,
+	post_processor: &[mlua::Function],
+	is_final: bool,
+	inner: Option<String>,
+) -> Result<String, Error> {
+	use lua::general_api::path::PathUserData;
+
+	// Converted layouts render around a placeholder instead, swapped for the content once converted.
+	let placeholder = inner.as_ref()
+		.filter(|_| converter.is_some())
+		.map(|inner| format!("jake-content-{:x}", incremental::hash(inner.as_bytes())));
+
+	if let Some(inner) = &inner {
+		let content = placeholder.clone().unwrap_or_else(|| inner.clone());
+		liquid_runtime.set_global("content".into(), liquid::model::Value::scalar(content));
+	}
+
+	let mut content = TemplateMirror::of(template)
+		.template.render(&liquid_runtime)?;
 
 	macro_rules! table {
 		($lua:expr $(, $key:ident = $value:expr)* $(,)?) => {
@@ -244,9 +795,18 @@ fn parse_content(
 	}
 
 	let info = table! { lua,
-		is_final = layout.is_nil(),
+		is_final = is_final,
 		source = source.as_option().map(PathUserData::from),
 	};
+
+	if let Some(converter) = converter {
+		content = converter.convert(&content, path, &info)?;
+	}
+
+	// Markdown puts a placeholder on a line of its own in a paragraph.
+	if let (Some(placeholder), Some(inner)) = (&placeholder, &inner) {
+		content = content.replace(&format!("<p>{placeholder}</p>"), inner).replace(placeholder, inner);
+	}
 	
 	for post in post_processor {
 		let context = || {
@@ -266,64 +826,39 @@ fn parse_content(
 		content.push_str(&result.to_str()?);
 	}
 
-	if let Some(layout) = liquid_runtime.try_get(&[ "layout".into() ]) && !layout.is_nil() {
-		let layout = layouts.get(layout.to_kstr().as_str()).ok_or_else(|| LayoutNotFound(layout.to_kstr().into()))?;
-
-		let mut frontmatter = liquid::to_object(&layout.frontmatter)?;
-		frontmatter.insert("layout".into(), liquid::model::Value::Nil);
-
-		let runtime = liquid_core::runtime::StackFrame::new(&liquid_runtime, frontmatter);
-
-		runtime.set_global("content".into(), liquid::model::Value::scalar(content));
-
-		content = parse_content(layouts, &layout.template, Some(&layout.path).into(), &runtime, lua, post_processor)
-			.into_error_result_with(|| format!("{} + {}", context(), layout.path))?;
-	}
-
 	Ok(content)
 }
 
-struct JakeLayout {
-	pub path: RelativePathBuf,
-	pub frontmatter: Option<FrontMatter>,
-	pub template: liquid::Template,
+/// Mirrors the private fields of `liquid::Template`,
+/// so templates can be rendered against a runtime of our own.
+struct TemplateMirror {
+	template: runtime::Template,
+	partials: Option<std::sync::Arc<dyn liquid_core::runtime::PartialStore + Send + Sync>>,
 }
 
-fn collect_layouts(config: &JakeConfig, parser: &liquid::Parser) -> Result<HashMap<KString, JakeLayout>, Error> {
-	let JakeConfig { layout_dir, .. } = config;
-
-	let mut layouts = HashMap::new();
-
-	let dir = walkdir::WalkDir::new(layout_dir)
-		.into_iter()
-		.filter_entry(|e| !e.file_name().to_string_lossy().starts_with('.'))
-		.filter_map(Result::ok)
-		.filter(|f| f.file_type().is_file());
-
-	for entry in dir {
-		let rel_path = entry.path()
-			.strip_prefix(layout_dir)
-			.ok()
-			.and_then(|p| RelativePathBuf::from_path(p).ok())
-			.ok_or_else(|| UnexpectedFilePath(entry.path().to_owned()))?;
+impl TemplateMirror {
+	fn of(template: &liquid::Template) -> &Self {
+		unsafe { std::mem::transmute::<&liquid::Template, &TemplateMirror>(template) } // :T
+	}
+}
 
-		let name = KString::from_ref(rel_path.file_stem().ok_or(Misc("Layout file has no name"))?);
+#[cfg(test)]
+mod tests {
+	use super::*;
 
-		let (frontmatter, content) = frontmatter::file_frontmatter_content(entry.path())
-			.into_error_result_with(|| rel_path.as_str())?
-			.ok_or(FileNotUtf8(rel_path.clone()))?;
+	#[test]
+	fn converted_layouts() {
+		let parser = liquid::ParserBuilder::with_stdlib().build().expect("Parser builds");
+		let layout = parser.parse("# {{ title }}\n\n{{ content }}\n\nAround {{ content }}.\n").expect("Layout parses");
 
-		let template = parser.parse(&content)
-			.into_error_result_with(|| rel_path.as_str())?;
+		let globals = liquid::object!({ "title": "Title" });
+		let runtime = liquid_core::runtime::RuntimeBuilder::new().set_globals(&globals).build();
+		let lua = mlua::Lua::new();
 
-		let layout = JakeLayout {
-			path: rel_path,
-			frontmatter,
-			template,
-		};
+		let inner = "<div>\n*as is*\n</div>";
+		let output = render_layer(&layout, &FileSource::<RelativePathBuf>::Lua, None, &runtime, &lua, Some(&Converter::markdown()), &[], true, Some(inner.to_owned()))
+			.expect("Layout renders");
 
-		layouts.insert(name, layout);
+		assert_eq!(output, format!("<h1>Title</h1>\n{inner}\n<p>Around {inner}.</p>\n"));
 	}
-
-	Ok(layouts)
 }
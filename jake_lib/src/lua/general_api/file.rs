@@ -9,6 +9,7 @@ pub const CONTENT_FIELD: &str = "content";
 pub const DATA_FIELD: &str = "data";
 pub const TO_WRITE_FIELD: &str = "to_write";
 pub const IGNORE_METHOD: &str = "ignore";
+pub const PAGINATE_METHOD: &str = "paginate";
 pub const POSTPROC_FIELD: &str = "post_proc";
 pub const IS_TEXT_FIELD: &str = "is_text";
 pub const IS_BIN_FIELD: &str = "is_binary";
@@ -142,6 +143,14 @@ impl UserData for FileUserData {
 			Ok(this.to_write = ignore.unwrap_or(false))
 		});
 
+		methods.add_method(PAGINATE_METHOD, |_, this, options: mlua::Table| {
+			if !options.contains_key("collection")? {
+				return Err(mlua::Error::runtime("`paginate` needs a `collection` to paginate"));
+			}
+
+			this.data.raw_set(crate::pagination::PAGINATE_KEY, options)
+		});
+
 		methods.add_function(super::NEW_FUNCTION, |lua, value: Option<mlua::Table>| {
 			if let Some(value) = value {
 				let content = value.get::<Option<_>>("content").transpose();
@@ -2,6 +2,50 @@ pub fn render_markdown(content: &str) -> String {
 	markdown::to_html_with_options(content, &markdown_ops()).expect("Basic Markdown doesn't error.")
 }
 
+/// Options for compiling Sass, shared by the built-in converter and the Lua `sass` function.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct SassOptions {
+	pub load_paths: Vec<std::path::PathBuf>,
+	/// Use the indented `.sass` syntax rather than SCSS.
+	pub indented: bool,
+	pub compressed: bool,
+}
+
+impl SassOptions {
+	/// Applies the options table given to the Lua `sass` function.
+	/// Relative load paths are resolved against `dir`.
+	pub fn extend_from_lua(mut self, table: &mlua::Table, dir: &std::path::Path) -> mlua::Result<Self> {
+		if let Some(paths) = table.get::<Option<Vec<String>>>("load_paths")? {
+			self.load_paths.extend(paths.into_iter().map(|p| dir.join(p)));
+		}
+
+		if let Some(indented) = table.get::<Option<bool>>("indented")? {
+			self.indented = indented;
+		}
+
+		match table.get::<Option<mlua::String>>("style")? {
+			Some(style) if style == "compressed" => self.compressed = true,
+			Some(style) if style == "expanded" => self.compressed = false,
+			Some(style) => return Err(mlua::Error::runtime(format!("Unknown Sass output style: '{}'", style.to_string_lossy()))),
+			None => (),
+		}
+
+		Ok(self)
+	}
+}
+
+pub fn compile_sass(content: &str, options: &SassOptions) -> Result<String, Box<grass::Error>> {
+	let mut grass_options = grass::Options::default()
+		.load_paths(&options.load_paths)
+		.style(if options.compressed { grass::OutputStyle::Compressed } else { grass::OutputStyle::Expanded });
+
+	if options.indented {
+		grass_options = grass_options.input_syntax(grass::InputSyntax::Sass);
+	}
+
+	grass::from_string(content, &grass_options)
+}
+
 pub fn minify_html(lua: &mlua::Lua, content: &str) -> mlua::Result<mlua::String> {
 	lua.create_string(minify_html::minify(content.as_bytes(), &minify_html_conf()))
 }
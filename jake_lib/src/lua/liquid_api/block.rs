@@ -57,30 +57,7 @@ impl liquid_core::Renderable for LuaBlockRenderer {
 				Ok(())
 			})?)?;
 
-			// env.set("get", scope.create_function_mut(|lua, key: String| {
-			// 	runtime.try_get(&[liquid::model::Scalar::new(key)])
-			// 		.map(|v| lua.to_value(&v.into_owned()))
-			// 		.unwrap_or(Ok(mlua::Value::Nil))
-			// })?)?;
-
-			// env.set("set", scope.create_function_mut(|_, (key, value): (String, mlua::Value)| {
-			// 	runtime.set_global(liquid::model::KString::new(key), value.into_owned());
-			// 	Ok(())
-			// })?);
-
-			// let env = self.lua.create_table()?;
-			// let mt = self.lua.create_table()?;
-			// mt.set("__index", self.lua.create_function_mut(|lua, (name, value): (mlua::String, Option<mlua::Value>)| {
-			// 	if let Some(value) = value {
-
-			// 	} else {
-
-			// 	}
-
-			// 	Ok(())
-			// })?)?;
-
-			self.func.call::<()>(())
+			context::with_context(&self.lua, runtime, || self.func.call::<()>(()))
 				.map_err(crate::error::ErrorExtensions::into_lua_error)?;
 
 			Ok(())
@@ -93,3 +70,73 @@ impl liquid_core::Renderable for LuaBlockRenderer {
 		Ok(())
 	}
 }
+
+/// A paired block tag registered from Lua, such as `{% callout warning %}...{% endcallout %}`.
+#[derive(Debug, Clone)]
+pub struct LuaCustomBlock {
+	pub start_tag: String,
+	pub end_tag: String,
+	pub func: super::tag::TagFunc,
+	pub lua: mlua::Lua,
+}
+
+impl LuaCustomBlock {
+	pub fn new(name: String, func: super::tag::TagFunc, lua: mlua::Lua) -> Self {
+		Self { end_tag: format!("end{name}"), start_tag: name, func, lua }
+	}
+}
+
+impl liquid_core::BlockReflection for LuaCustomBlock {
+	fn start_tag(&self) -> &str {
+		&self.start_tag
+	}
+
+	fn end_tag(&self) -> &str {
+		&self.end_tag
+	}
+
+	fn description(&self) -> &str {
+		"Custom block registered from Lua"
+	}
+}
+
+impl liquid_core::ParseBlock for LuaCustomBlock {
+	fn parse(&self, arguments: liquid_core::TagTokenIter, mut block: liquid_core::TagBlock, options: &liquid_core::Language) -> liquid_core::Result<Box<dyn liquid_core::Renderable>> {
+		let args = super::tag::TagArgs::parse(arguments, &self.func)?;
+
+		let body = liquid_core::runtime::Template::new(block.parse_all(options)?);
+		block.assert_empty();
+
+		Ok(Box::new(LuaCustomBlockRenderer { tag: self.start_tag.clone(), func: self.func.func.clone(), args, body, lua: self.lua.clone() }))
+	}
+
+	fn reflection(&self) -> &dyn liquid_core::BlockReflection {
+		self
+	}
+}
+
+#[derive(Debug)]
+struct LuaCustomBlockRenderer {
+	tag: String,
+	args: super::tag::TagArgs,
+	body: liquid_core::runtime::Template,
+	func: mlua::Function,
+	lua: mlua::Lua,
+}
+
+impl liquid_core::Renderable for LuaCustomBlockRenderer {
+	fn render_to(&self, writer: &mut dyn std::io::Write, runtime: &dyn liquid_core::Runtime) -> liquid_core::Result<()> {
+		// The body renders first, so the function gets the finished markup.
+		let body = self.body.render(runtime)?;
+
+		let mut args = self.args.evaluate(&self.lua, runtime)?;
+		args.push_front(mlua::Value::String(self.lua.create_string(body).map_err(Error::from)?));
+
+		let res: mlua::Value = context::with_context(&self.lua, runtime, || self.func.call(args)).map_err(|e| super::tag::lua_error(&self.tag, e))?;
+		let res = res.to_string().map_err(|e| super::tag::lua_error(&self.tag, e))?;
+
+		writer.write_all(res.as_bytes()).map_err(Error::from)?;
+
+		Ok(())
+	}
+}
@@ -0,0 +1,217 @@
+use crate::error::ResultExtensions;
+use liquid::model::{Scalar, Value};
+use liquid_core::{Runtime, ValueView};
+use mlua::{LuaSerdeExt, ObjectLike};
+
+const CONTEXT_GLOBAL: &str = "CONTEXT";
+/// Set on the metatable of every view, so assigning a view copies what it stands for.
+const VIEW_MARKER: &str = "__jake_context";
+
+/// The variables of the template being rendered, as `CONTEXT` while a Lua tag, block or filter runs.
+///
+/// Reading a field looks the variable up, such as `page` or `forloop`.
+/// Objects and arrays come back as views of the template's own, with array items starting at 1 as in Lua.
+/// Calling a view gives a plain copy of it instead, e.g. to go through it with `ipairs`.
+///
+/// Assigning a field sets it for the rest of the page being rendered, like `assign`, including fields of fields.
+/// That goes for `page` and `site` too, which can be changed or replaced outright,
+/// but only as this page sees them; other pages and the site itself are left alone.
+fn view<'scope, 'env>(
+	lua: &mlua::Lua,
+	scope: &'scope mlua::Scope<'scope, 'env>,
+	runtime: &'env dyn Runtime,
+	path: Vec<Scalar>,
+) -> mlua::Result<mlua::Table> {
+	let metatable = lua.create_table()?;
+	metatable.raw_set(VIEW_MARKER, true)?;
+
+	let index_path = path.clone();
+	metatable.raw_set("__index", scope.create_function(move |lua, (_, key): (mlua::Value, mlua::Value)| {
+		let Some(key) = key_of(&key, index_path.is_empty()) else {
+			return Ok(mlua::Value::Nil);
+		};
+
+		let path: Vec<_> = index_path.iter().cloned().chain([ key ]).collect();
+
+		match runtime.try_get(&path) {
+			Some(value) if value.as_object().is_some() || value.as_array().is_some() => Ok(mlua::Value::Table(view(lua, scope, runtime, path)?)),
+			Some(value) => to_lua(lua, &value.to_value()),
+			None => Ok(mlua::Value::Nil),
+		}
+	})?)?;
+
+	let new_index_path = path.clone();
+	metatable.raw_set("__newindex", scope.create_function(move |lua, (_, key, value): (mlua::Value, mlua::Value, mlua::Value)| {
+		let key = key_of(&key, new_index_path.is_empty())
+			.ok_or_else(|| mlua::Error::runtime(format!("Can't assign to CONTEXT with a key of type {}", key.type_name())))?;
+
+		let path: Vec<_> = new_index_path.iter().cloned().chain([ key ]).collect();
+		assign(runtime, &path, to_liquid(lua, value)?)
+	})?)?;
+
+	metatable.raw_set("__call", scope.create_function(move |lua, _: mlua::MultiValue| {
+		let value = match path.as_slice() {
+			[] => Some(Value::Object(runtime.roots().into_iter()
+				.map(|root| root.into_owned())
+				.filter_map(|root| runtime.try_get(&[ Scalar::new(root.clone()) ]).map(|value| (root, value.to_value())))
+				.collect())),
+			path => runtime.try_get(path).map(|value| value.to_value()),
+		};
+
+		value.map_or(Ok(mlua::Value::Nil), |value| to_lua(lua, &value))
+	})?)?;
+
+	let view = lua.create_table()?;
+	view.set_metatable(Some(metatable));
+
+	Ok(view)
+}
+
+/// A key into a view, with Lua's array items counted from 1 and Liquid's from 0.
+/// Only names are variables, at the top of `CONTEXT`.
+fn key_of(key: &mlua::Value, is_root: bool) -> Option<Scalar> {
+	let item = match key {
+		mlua::Value::String(name) => return Some(Scalar::new(name.to_str().ok()?.to_owned())),
+		mlua::Value::Integer(item) => *item,
+		mlua::Value::Number(item) if item.fract() == 0.0 => *item as i64,
+		_ => return None,
+	};
+
+	(!is_root && item >= 1).then(|| Scalar::new(item - 1))
+}
+
+/// Sets the value at `path`, a field or item of a variable or the variable itself.
+fn assign(runtime: &dyn Runtime, path: &[Scalar], value: Value) -> mlua::Result<()> {
+	let (root, path) = path.split_first().ok_or_else(|| mlua::Error::runtime("Can't assign to CONTEXT itself"))?;
+
+	let Some((last, path)) = path.split_last() else {
+		runtime.set_global(root.to_kstr().into(), value);
+		return Ok(());
+	};
+
+	let not_found = || mlua::Error::runtime(format!("Can't assign to '{}' in '{}', as it's not an object or array", last.to_kstr(), root.to_kstr()));
+
+	let mut variable = runtime.try_get(std::slice::from_ref(root)).ok_or_else(not_found)?.into_owned();
+
+	let mut target = &mut variable;
+	for key in path {
+		target = match target {
+			Value::Object(object) => object.get_mut(key.to_kstr().as_str()),
+			Value::Array(array) => item_of(key).and_then(|item| array.get_mut(item)),
+			_ => None,
+		}.ok_or_else(not_found)?;
+	}
+
+	match target {
+		Value::Object(object) if value.is_nil() => { object.remove(last.to_kstr().as_str()); },
+		Value::Object(object) => { object.insert(last.to_kstr().into(), value); },
+		// Assigning just past the end appends, like it would to a Lua table.
+		Value::Array(array) => match item_of(last) {
+			Some(item) if item < array.len() => array[item] = value,
+			Some(item) if item == array.len() => array.push(value),
+			_ => return Err(mlua::Error::runtime(format!("Can't assign to item {} of an array of {} in '{}'", last.to_kstr(), array.len(), root.to_kstr()))),
+		},
+		_ => return Err(not_found()),
+	}
+
+	runtime.set_global(root.to_kstr().into(), variable);
+
+	Ok(())
+}
+
+fn item_of(key: &Scalar) -> Option<usize> {
+	key.to_integer().and_then(|item| usize::try_from(item).ok())
+}
+
+fn to_lua(lua: &mlua::Lua, value: &Value) -> mlua::Result<mlua::Value> {
+	// Nil is `nil` in Lua, rather than the `null` light userdata, which is truthy.
+	lua.to_value_with(value, mlua::SerializeOptions::new().serialize_none_to_null(false).serialize_unit_to_null(false))
+}
+
+fn to_liquid(lua: &mlua::Lua, value: mlua::Value) -> mlua::Result<Value> {
+	let value = match value {
+		mlua::Value::Table(table) if table.metatable().is_some_and(|meta| meta.raw_get(VIEW_MARKER).unwrap_or(false)) => table.call(())?,
+		value => value,
+	};
+
+	super::liquid_view::LuaValueView::new(value, lua)?.to_value_checked().into_lua_result()
+}
+
+/// Runs `f` with `CONTEXT` standing for `runtime`, putting back whatever was there before.
+pub fn with_context<R>(lua: &mlua::Lua, runtime: &dyn Runtime, f: impl FnOnce() -> mlua::Result<R>) -> mlua::Result<R> {
+	lua.scope(|scope| {
+		let globals = lua.globals();
+		let outer: mlua::Value = globals.get(CONTEXT_GLOBAL)?;
+
+		globals.set(CONTEXT_GLOBAL, view(lua, scope, runtime, Vec::new())?)?;
+		let result = f();
+		globals.set(CONTEXT_GLOBAL, outer)?;
+
+		result
+	})
+}
+
+#[cfg(test)]
+mod tests {
+	use super::*;
+
+	fn render(template: &str) -> Result<String, liquid::Error> {
+		let lua = mlua::Lua::new();
+		let parser = liquid::ParserBuilder::with_stdlib().block(super::super::block::LuaBlock { lua }).build()?;
+
+		let page = liquid::model::value!({ "title": "Title", "tags": [ "a", "b" ], "draft": false });
+		let globals = liquid::object!({ "page": page.clone(), "site": { "title": "Site" } });
+
+		let output = parser.parse(template)?.render(&globals)?;
+
+		// Writes only change what the template sees.
+		match globals.get("page") == Some(&page) {
+			true => Ok(output),
+			false => Err(liquid::Error::with_msg("The globals were changed")),
+		}
+	}
+
+	#[test]
+	fn reads() {
+		let output = render(r#"{% lua %}
+			write(tostring(CONTEXT.page.missing == nil), " ", tostring(CONTEXT.missing == nil), " ", tostring(CONTEXT.page.draft), " ")
+			write(CONTEXT.page.title, " ", CONTEXT.page.tags[1], " ", tostring(CONTEXT.page.tags[3] == nil), " ", CONTEXT.page.tags.size, " ")
+			write(#CONTEXT.page.tags(), " ", CONTEXT().site.title)
+		{% endlua %}"#).expect("Template renders");
+
+		assert_eq!(output, "true true false Title a true 2 2 Site");
+	}
+
+	#[test]
+	fn writes() {
+		let output = render(r#"{% lua %}
+			CONTEXT.page.title = "Changed"
+			CONTEXT.page.tags[1] = "z"
+			CONTEXT.page.tags[3] = "c"
+			CONTEXT.page.extra = { count = 1 }
+			CONTEXT.page.extra.count = 2
+			CONTEXT.page.draft = nil
+			CONTEXT.greeting = "Hi"
+			CONTEXT.copy = CONTEXT.site
+		{% endlua %}{{ page.title }} {{ page.tags | join: "," }} {{ page.extra.count }} {% if page contains "draft" %}kept{% else %}gone{% endif %} {{ greeting }} {{ copy.title }}"#)
+			.expect("Template renders");
+
+		assert_eq!(output, "Changed z,b,c 2 gone Hi Site");
+	}
+
+	#[test]
+	fn replacing_globals() {
+		let output = render(r#"{% lua %}CONTEXT.page = { title = "New" }; CONTEXT.site = nil{% endlua %}{{ page.title }} {% if page contains "tags" %}kept{% else %}gone{% endif %} {{ site }}|"#)
+			.expect("Template renders");
+
+		assert_eq!(output, "New gone |");
+	}
+
+	#[test]
+	fn invalid_writes() {
+		assert!(render("{% lua %}CONTEXT.page.tags[5] = 'e'{% endlua %}").is_err());
+		assert!(render("{% lua %}CONTEXT.page.title.first = 't'{% endlua %}").is_err());
+		assert!(render("{% lua %}CONTEXT.missing.field = 1{% endlua %}").is_err());
+		assert!(render("{% lua %}CONTEXT[1] = 1{% endlua %}").is_err());
+	}
+}
@@ -78,7 +78,7 @@ impl Filter for LuaFilter {
 
 		let key_args = self.lua.create_table_from(key_args).map_err(Error::from)?;
 
-		let result: mlua::Value = self.func.call((input, key_args, pos_args)).map_err(Error::from)?;
+		let result: mlua::Value = context::with_context(&self.lua, runtime, || self.func.call((input, key_args, pos_args))).map_err(Error::from)?;
 
 		let result = liquid::model::to_value(&result)?;
 
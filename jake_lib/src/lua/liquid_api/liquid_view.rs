@@ -49,6 +49,11 @@ impl LuaValueView {
 	pub fn new(value: impl mlua::IntoLua, lua: &mlua::Lua) -> mlua::Result<Self> {
 		Ok(Self(value.into_lua(lua)?))
 	}
+
+	/// Converts the value to Liquid like [`ValueView::to_value`], failing rather than falling back to nil.
+	pub fn to_value_checked(&self) -> liquid_core::Result<liquid_core::Value> {
+		liquid::model::to_value(&self.0)
+	}
 }
 
 impl ValueView for LuaValueView {
@@ -86,7 +91,7 @@ impl ValueView for LuaValueView {
 	}
 
 	fn to_value(&self) -> liquid_core::Value {
-		liquid::model::to_value(&self.0).unwrap_or(liquid_core::Value::Nil)
+		self.to_value_checked().unwrap_or(liquid_core::Value::Nil)
 	}
 	
 	fn as_scalar(&self) -> Option<liquid::model::ScalarCow<'_>> {
@@ -1,6 +1,7 @@
 pub mod tag;
 pub mod filter;
 pub mod block;
+pub mod context;
 // pub mod converter;
 // pub mod liquid_user_data;
 pub mod liquid_view;
@@ -1,5 +1,5 @@
 use super::*;
-use mlua::LuaSerdeExt;
+use mlua::{FromLua, LuaSerdeExt};
 use liquid_core::{
 	Expression,
 	Language,
@@ -10,10 +10,120 @@ use liquid_core::{
 	TagTokenIter,
 };
 
+const FUNC_FIELD: &str = "func";
+const RAW_FIELD: &str = "raw";
+const KEYWORDS_FIELD: &str = "keywords";
+
+/// A function registered in `TAGS` or `BLOCKS`.
+///
+/// Either the function itself, or a table holding it as `func` along with options:
+/// `keywords = true` to take `key: value` arguments as a table before the positional ones,
+/// or `raw = true` to have it parse the tag's markup itself, which must still lex as Liquid.
+#[derive(Debug, Clone)]
+pub struct TagFunc {
+	pub func: mlua::Function,
+	pub raw: bool,
+	pub keywords: bool,
+}
+
+impl FromLua for TagFunc {
+	fn from_lua(value: mlua::Value, _: &mlua::Lua) -> mlua::Result<Self> {
+		match value {
+			mlua::Value::Function(func) => Ok(Self { func, raw: false, keywords: false }),
+			mlua::Value::Table(table) => Ok(Self {
+				func: table.get(FUNC_FIELD)?,
+				raw: table.get::<Option<bool>>(RAW_FIELD)?.unwrap_or(false),
+				keywords: table.get::<Option<bool>>(KEYWORDS_FIELD)?.unwrap_or(false),
+			}),
+			value => Err(mlua::Error::runtime(format!("Expected a function or a table for a tag, got {:?}", value.type_name()))),
+		}
+	}
+}
+
+/// The arguments of a tag or block from Lua.
+#[derive(Debug)]
+pub enum TagArgs {
+	Parsed {
+		positional: Vec<Expression>,
+		/// `None` unless the function takes keyword arguments.
+		keyword: Option<Vec<(String, Expression)>>,
+	},
+	/// The markup of the tag as written.
+	Raw(String),
+}
+
+impl TagArgs {
+	/// Reads positional arguments, and `key: value` ones if the function takes them,
+	/// in any order and optionally separated by commas.
+	pub fn parse(arguments: TagTokenIter, func: &TagFunc) -> liquid_core::Result<Self> {
+		if func.raw {
+			return Ok(Self::Raw(markup(arguments)));
+		}
+
+		let mut positional = vec![];
+		let mut keyword = func.keywords.then(Vec::new);
+
+		let mut tokens = arguments.peekable();
+		while let Some(token) = tokens.next() {
+			if token.as_str() == "," {
+				continue;
+			}
+
+			if tokens.peek().is_some_and(|next| next.as_str() == ":") {
+				let key = token.expect_identifier().into_result()?.to_owned();
+				let Some(keyword) = &mut keyword else {
+					return Err(liquid_core::Error::with_msg(format!("Unexpected keyword argument '{key}', the tag needs `{KEYWORDS_FIELD} = true` to take them")));
+				};
+				tokens.next();
+
+				let value = tokens.next()
+					.ok_or_else(|| liquid_core::Error::with_msg(format!("Expected a value for '{key}'")))?
+					.expect_value().into_result()?;
+
+				keyword.push((key, value));
+			} else {
+				positional.push(token.expect_value().into_result()?);
+			}
+		}
+
+		Ok(Self::Parsed { positional, keyword })
+	}
+
+	/// What the function is called with: the positional arguments, after a table of the keyword ones
+	/// if it takes them, or just the markup if it's raw.
+	pub fn evaluate(&self, lua: &mlua::Lua, runtime: &dyn Runtime) -> Result<mlua::MultiValue, Error> {
+		let (positional, keyword) = match self {
+			Self::Raw(markup) => return Ok(mlua::MultiValue::from_iter([ mlua::Value::String(lua.create_string(markup)?) ])),
+			Self::Parsed { positional, keyword } => (positional, keyword),
+		};
+
+		let mut args: mlua::MultiValue = positional.iter().map(|arg| arg.evaluate(runtime))
+			.map(|arg| lua.to_value(&arg?.into_owned()).map_err(Error::from))
+			.try_collect()?;
+
+		if let Some(keyword) = keyword {
+			let named = lua.create_table()?;
+			for (key, value) in keyword {
+				named.set(key.as_str(), lua.to_value(&value.evaluate(runtime)?.into_owned())?)?;
+			}
+
+			args.push_front(mlua::Value::Table(named));
+		}
+
+		Ok(args)
+	}
+}
+
+/// The markup of a tag, as its tokens with a space between each.
+/// The spacing between them isn't kept, as the parser only hands over tokens.
+fn markup(arguments: TagTokenIter) -> String {
+	arguments.map(|token| token.as_str().to_owned()).collect::<Vec<_>>().join(" ")
+}
+
 #[derive(Debug, Clone)]
 pub struct LuaTag {
 	pub tag: String,
-	pub func: mlua::Function,
+	pub func: TagFunc,
 	pub lua: mlua::Lua,
 }
 
@@ -28,14 +138,10 @@ impl TagReflection for LuaTag {
 }
 
 impl ParseTag for LuaTag {
-	fn parse(&self, mut arguments: TagTokenIter, _options: &Language) -> liquid_core::Result<Box<dyn Renderable>> {
-		let mut args = vec![];
+	fn parse(&self, arguments: TagTokenIter, _options: &Language) -> liquid_core::Result<Box<dyn Renderable>> {
+		let args = TagArgs::parse(arguments, &self.func)?;
 
-		while let Ok(arg) = arguments.expect_next("") {
-			args.push(arg.expect_value().into_result()?);
-		}
-		
-		Ok(Box::new(LuaTagRenderer { func: self.func.clone(), args, lua: self.lua.clone() }))
+		Ok(Box::new(LuaTagRenderer { tag: self.tag.clone(), func: self.func.func.clone(), args, lua: self.lua.clone() }))
 	}
 
 	fn reflection(&self) -> &dyn TagReflection {
@@ -45,22 +151,110 @@ impl ParseTag for LuaTag {
 
 #[derive(Debug)]
 struct LuaTagRenderer {
-	args: Vec<Expression>,
+	tag: String,
+	args: TagArgs,
 	func: mlua::Function,
 	lua: mlua::Lua,
 }
 
 impl Renderable for LuaTagRenderer {
 	fn render_to(&self, writer: &mut dyn std::io::Write, runtime: &dyn Runtime) -> liquid_core::Result<()> {
-		let args: mlua::MultiValue = self.args.iter().map(|arg| arg.evaluate(runtime))
-			.map(|arg| self.lua.to_value(&arg?.into_owned()).map_err(Error::from))
-			.try_collect()?;
+		let args = self.args.evaluate(&self.lua, runtime)?;
+
+		let res: mlua::Value = context::with_context(&self.lua, runtime, || self.func.call(args)).map_err(|e| lua_error(&self.tag, e))?;
+		let res = res.to_string().map_err(|e| lua_error(&self.tag, e))?;
 
-		let res: mlua::Value = self.func.call(args).map_err(Error::from)?;
-		let res = res.to_string().map_err(Error::from)?;
-		
 		writer.write_all(res.as_bytes()).map_err(Error::from)?;
 
 		Ok(())
 	}
 }
+
+/// An error from the Lua function of a tag or block, tracing it back to the tag.
+pub(super) fn lua_error(tag: &str, error: mlua::Error) -> liquid_core::Error {
+	liquid_core::Error::from(Error::from(error)).trace(format!("{{% {tag} %}}"))
+}
+
+#[cfg(test)]
+mod tests {
+	use super::*;
+
+	/// Joins whatever the tag is called with, keyword tables sorted by key.
+	const ECHO: &str = r##"
+		return function(...)
+			local out = {}
+			for i = 1, select("#", ...) do
+				local arg = select(i, ...)
+				if type(arg) == "table" then
+					local keys = {}
+					for key in pairs(arg) do table.insert(keys, key) end
+					table.sort(keys)
+					for j, key in ipairs(keys) do keys[j] = key .. "=" .. tostring(arg[key]) end
+					arg = "{" .. table.concat(keys, ",") .. "}"
+				end
+				table.insert(out, tostring(arg))
+			end
+			return table.concat(out, "|")
+		end
+	"##;
+
+	fn render(raw: bool, keywords: bool, template: &str) -> Result<String, liquid::Error> {
+		let lua = mlua::Lua::new();
+		let func = lua.load(ECHO).eval().expect("Echo function loads");
+		let tag = LuaTag { tag: String::from("echo"), func: TagFunc { func, raw, keywords }, lua };
+
+		let parser = liquid::ParserBuilder::with_stdlib().tag(tag).build()?;
+		parser.parse(template)?.render(&liquid::object!({ "name": "Pengu" }))
+	}
+
+	fn render_block(func: &str, keywords: bool, template: &str) -> Result<String, liquid::Error> {
+		let lua = mlua::Lua::new();
+		let func = lua.load(func).eval().expect("Block function loads");
+		let block = super::super::block::LuaCustomBlock::new(String::from("wrap"), TagFunc { func, raw: false, keywords }, lua);
+
+		let parser = liquid::ParserBuilder::with_stdlib().block(block).build()?;
+		parser.parse(template)?.render(&liquid::object!({ "name": "Pengu" }))
+	}
+
+	#[test]
+	fn positional() {
+		assert_eq!(render(false, false, "{% echo 1, name 'bird' %}").expect("Tag renders"), "1|Pengu|bird");
+		assert_eq!(render(false, false, "{% echo %}").expect("Tag renders"), "");
+		assert!(render(false, false, "{% echo size: 2 %}").is_err());
+	}
+
+	#[test]
+	fn keywords() {
+		assert_eq!(render(false, true, "{% echo 1, size: 2 name, flag: true 'bird' %}").expect("Tag renders"), "{flag=true,size=2}|1|Pengu|bird");
+		assert_eq!(render(false, true, "{% echo name %}").expect("Tag renders"), "{}|Pengu");
+		assert!(render(false, true, "{% echo size: %}").is_err());
+		assert!(render(false, true, "{% echo 'size': 2 %}").is_err());
+	}
+
+	#[test]
+	fn raw() {
+		assert_eq!(render(true, false, "{% echo  pengu  >   bird, \"a  b\" %}").expect("Tag renders"), "pengu > bird , \"a  b\"");
+		assert_eq!(render(true, false, "{% echo name %}").expect("Tag renders"), "name");
+		assert_eq!(render(true, false, "{% echo %}").expect("Tag renders"), "");
+		// The markup still has to lex as Liquid.
+		assert!(render(true, false, "{% echo user/1234 %}").is_err());
+	}
+
+	#[test]
+	fn blocks() {
+		let output = render_block(ECHO, false, "{% wrap 1, name %}Hi {{ name }}{% endwrap %}").expect("Block renders");
+		assert_eq!(output, "Hi Pengu|1|Pengu");
+
+		let output = render_block(ECHO, true, "{% wrap size: 2 'bird' %}{% endwrap %}").expect("Block renders");
+		assert_eq!(output, "|{size=2}|bird");
+	}
+
+	#[test]
+	fn block_errors() {
+		let error = render_block("return function() error('boom') end", false, "{% wrap %}body{% endwrap %}")
+			.expect_err("The block fails");
+
+		let message = error.to_string();
+		assert!(message.contains("boom") && message.contains("{% wrap"), "Expected the Lua error and the block, got {message}");
+	}
+}
@@ -2,8 +2,9 @@ pub mod typed;
 pub mod general_api;
 pub mod liquid_api;
 
-use crate::{JakeConfig, data_strctures::{JakeFileT1, JakeFileT2}, error::{Error, JakeError, ResultExtensions}};
+use crate::{JakeConfig, converters::Converter, data_strctures::{JakeFileT1, JakeFileT2}, error::{Error, JakeError, ResultExtensions}, frontmatter::FrontMatter};
 use general_api::{file::FileUserData, path::PathUserData};
+use mlua::LuaSerdeExt;
 
 const INIT_LUA_PATHS: &[&str] = &[
 	"init.lua",
@@ -12,6 +13,7 @@ const INIT_LUA_PATHS: &[&str] = &[
 
 // Global variable names.
 const TAGS_TABLE: &str = "TAGS";
+const BLOCKS_TABLE: &str = "BLOCKS";
 const FILTERS_TABLE: &str = "FILTERS";
 const CONVERTERS_TABLE: &str = "CONVERTERS";
 const SITE_DATA: &str = "SITE";
@@ -19,6 +21,7 @@ const POST_PROCESSOR: &str = "POST_PROC";
 
 const MINIFY_HTML_FUNC: &str = "minify";
 const RENDER_MARKDOWN_FUNC: &str = "render";
+const COMPILE_SASS_FUNC: &str = "sass";
 
 // Site data keys.
 const DIR_PROJ: &str = "project_dir";
@@ -26,19 +29,29 @@ const DIR_SRC: &str = "source_dir";
 const DIR_OUT: &str = "output_dir";
 const DIR_PLUG: &str = "plugins_dir";
 const DIR_LAY: &str = "layout_dir";
+const DIR_INC: &str = "includes_dir";
+const DIR_DATA: &str = "data_dir";
 const FILES: &str = "files";
+const CONFIG: &str = "config";
+const DATA: &str = "data";
 
 #[derive(Debug, Clone, Default)]
 pub struct LuaResult {
-	pub tags: Vec<(String, mlua::Function)>,
-	pub converters: Vec<(String, mlua::Function)>,
+	pub tags: Vec<(String, liquid_api::tag::TagFunc)>,
+	pub blocks: Vec<(String, liquid_api::tag::TagFunc)>,
+	/// `None` marks a converter disabled from Lua by setting it to `false`.
+	pub converters: Vec<(String, Option<Converter>)>,
 	pub filters: Vec<(String, mlua::Function)>,
 	pub post_processors: Option<mlua::Function>,
 
 	pub files: Vec<JakeFileT2>,
+	/// The site globals from `jake.yml`, as left by the plugins.
+	pub globals: FrontMatter,
+	/// The contents of the data directory, as left by the plugins.
+	pub data: serde_json::Map<String, serde_json::Value>,
 }
 
-pub fn setup_lua_state(lua: &mlua::Lua, config: &JakeConfig, files: Vec<JakeFileT1>) -> Result<LuaResult, Error> {
+pub fn setup_lua_state(lua: &mlua::Lua, config: &JakeConfig, files: Vec<JakeFileT1>, data: serde_json::Map<String, serde_json::Value>) -> Result<LuaResult, Error> {
 	let Some(init_file) = INIT_LUA_PATHS.iter()
 		.map(|path| config.plugins_dir.join(path))
 		.find(|path| path.exists()) else {
@@ -46,6 +59,8 @@ pub fn setup_lua_state(lua: &mlua::Lua, config: &JakeConfig, files: Vec<JakeFile
 				files: files.into_iter()
 					.flat_map(|f| FileUserData::from_file(f, lua).map(|f| f.into_file(lua)))
 					.collect::<Result<_, _>>()?,
+				globals: config.globals.clone(),
+				data,
 				..Default::default()
 			});
 		};
@@ -64,10 +79,22 @@ pub fn setup_lua_state(lua: &mlua::Lua, config: &JakeConfig, files: Vec<JakeFile
 	global.set(RENDER_MARKDOWN_FUNC, mlua::Function::wrap(|content: mlua::String| Ok(general_api::formatting::render_markdown(&content.to_str()?))))?;
 	global.set(MINIFY_HTML_FUNC, lua.create_function(|lua, html: mlua::String| general_api::formatting::minify_html(lua, &html.to_str()?))?)?;
 
+	let sass_options = general_api::formatting::SassOptions { load_paths: config.sass_load_paths.clone(), ..Default::default() };
+	let project_dir = config.project_dir.clone();
+	global.set(COMPILE_SASS_FUNC, lua.create_function(move |_, (content, options): (mlua::String, Option<mlua::Table>)| {
+		let options = match options {
+			Some(table) => sass_options.clone().extend_from_lua(&table, &project_dir)?,
+			None => sass_options.clone(),
+		};
+
+		general_api::formatting::compile_sass(&content.to_str()?, &options).into_lua_result()
+	})?)?;
+
 	global.set(PathUserData::CLASS_NAME, lua.create_proxy::<PathUserData>()?)?;
 	global.set(FileUserData::CLASS_NAME, lua.create_proxy::<FileUserData>()?)?;
 
 	global.set(TAGS_TABLE, lua.create_table()?)?;
+	global.set(BLOCKS_TABLE, lua.create_table()?)?;
 	global.set(FILTERS_TABLE, lua.create_table()?)?;
 	global.set(CONVERTERS_TABLE, lua.create_table()?)?;
 
@@ -77,6 +104,11 @@ pub fn setup_lua_state(lua: &mlua::Lua, config: &JakeConfig, files: Vec<JakeFile
 	site_data.set(DIR_OUT, config.output_dir.as_os_str())?;
 	site_data.set(DIR_PLUG, config.plugins_dir.as_os_str())?;
 	site_data.set(DIR_LAY, config.layout_dir.as_os_str())?;
+	site_data.set(DIR_INC, config.includes_dir.as_os_str())?;
+	site_data.set(DIR_DATA, config.data_dir.as_os_str())?;
+
+	site_data.set(CONFIG, lua.to_value(&config.globals)?)?;
+	site_data.set(DATA, lua.to_value(&data)?)?;
 
 	let site_files = lua.create_table_from(
 		files.into_iter().enumerate().map(|(i, file)| (i + 1, FileUserData::from_file(file, lua).expect("Userdata failed uwu"))) //TODO: Iter tools thing
@@ -89,7 +121,7 @@ pub fn setup_lua_state(lua: &mlua::Lua, config: &JakeConfig, files: Vec<JakeFile
 	// })?;
 	// site_data.set(NEW_FILE, new_file)?;
 
-	global.set(SITE_DATA, site_data)?;
+	global.set(SITE_DATA, &site_data)?;
 
 	lua.load(&init)
 		.set_name(init_file.strip_prefix(&config.project_dir)
@@ -98,8 +130,14 @@ pub fn setup_lua_state(lua: &mlua::Lua, config: &JakeConfig, files: Vec<JakeFile
 		.exec()?;
 
 	let tags = global.get::<mlua::Table>(TAGS_TABLE)?.pairs().try_collect()?;
+	let blocks = global.get::<mlua::Table>(BLOCKS_TABLE)?.pairs().try_collect()?;
 	let filters = global.get::<mlua::Table>(FILTERS_TABLE)?.pairs().try_collect()?;
-	let converters = global.get::<mlua::Table>(CONVERTERS_TABLE)?.pairs().try_collect()?;
+	let converters = global.get::<mlua::Table>(CONVERTERS_TABLE)?.pairs::<String, mlua::Value>()
+		.map(|pair| pair.and_then(|(ext, value)| match value {
+			mlua::Value::Boolean(false) => Ok((ext, None)),
+			value => Ok((ext.clone(), Some(lua.unpack(value).into_error_result_with(|| format!("{CONVERTERS_TABLE}.{ext}")).into_lua_result()?))),
+		}))
+		.try_collect()?;
 
 	let files: Vec<JakeFileT2> = site_files.sequence_values().map(|f|
 		f.and_then(|f: FileUserData| {
@@ -127,5 +165,11 @@ pub fn setup_lua_state(lua: &mlua::Lua, config: &JakeConfig, files: Vec<JakeFile
 
 	let post_processors = global.get(POST_PROCESSOR)?;
 
-	Ok(LuaResult { tags, converters, filters, files, post_processors})
+	// Changes to the site config flow back into the Liquid globals.
+	let globals = lua.from_value(site_data.get(CONFIG)?)
+		.into_error_result_with(|| format!("{SITE_DATA}.{CONFIG}"))?;
+	let data = lua.from_value(site_data.get(DATA)?)
+		.into_error_result_with(|| format!("{SITE_DATA}.{DATA}"))?;
+
+	Ok(LuaResult { tags, blocks, converters, filters, files, post_processors, globals, data })
 }
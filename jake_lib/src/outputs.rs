@@ -0,0 +1,129 @@
+use crate::{data_strctures::FileSource, error::{Error, JakeError, ResultExtensions}, JakeConfig};
+use globset::GlobSetBuilder;
+use relative_path::{Component, RelativePath, RelativePathBuf};
+use std::collections::{BTreeMap, BTreeSet};
+
+const MANIFEST_FILE: &str = "manifest.json";
+
+/// Makes sure no two files write to the same output, and none writes outside the output directory.
+///
+/// Outputs are compared after normalizing, so `a/../b.html` clashes with `b.html`.
+pub fn check_outputs<'a>(outputs: impl IntoIterator<Item = (&'a RelativePath, &'a FileSource)>) -> Result<(), Error> {
+	let mut sources: BTreeMap<RelativePathBuf, Vec<FileSource>> = BTreeMap::new();
+
+	for (output, source) in outputs {
+		let normalized = output.normalize();
+
+		if normalized.components().next().is_none_or(|c| c == Component::ParentDir) {
+			return Err(JakeError::OutputOutsideDir(output.to_owned(), source.clone()).into());
+		}
+
+		sources.entry(normalized).or_default().push(source.clone());
+	}
+
+	match sources.into_iter().find(|(_, sources)| sources.len() > 1) {
+		Some((output, sources)) => Err(JakeError::OutputCollision(output, sources).into()),
+		None => Ok(()),
+	}
+}
+
+/// Deletes what the last build wrote that this one didn't, then records this build's outputs for the next.
+///
+/// Only outputs listed in the last build's manifest are deleted, never files written by anything else,
+/// and not those matching `keep_files` either. Directories left empty go with them.
+/// Returns how many outputs were deleted.
+pub fn remove_stale(config: &JakeConfig, outputs: BTreeSet<RelativePathBuf>) -> Result<usize, Error> {
+	let manifest_path = config.cache_dir.join(MANIFEST_FILE);
+
+	let previous: BTreeSet<RelativePathBuf> = match std::fs::File::open(&manifest_path) {
+		Ok(file) => serde_json::from_reader(std::io::BufReader::new(file)).unwrap_or_default(),
+		Err(_) => BTreeSet::new(),
+	};
+
+	let keep = config.keep_files.iter()
+		.fold(GlobSetBuilder::new(), |mut builder, glob| { builder.add(glob.clone()); builder })
+		.build()?;
+
+	let mut removed = 0;
+
+	for stale in previous.difference(&outputs) {
+		// A kept directory keeps everything in it.
+		let kept = std::iter::successors(Some(stale.as_relative_path()), |p| p.parent())
+			.any(|p| keep.is_match(p.as_str()));
+
+		let path = stale.to_logical_path(&config.output_dir);
+		if kept || !path.is_file() {
+			continue;
+		}
+
+		std::fs::remove_file(&path).into_error_result_with(|| stale.as_str())?;
+		removed += 1;
+
+		for dir in path.ancestors().skip(1).take_while(|dir| *dir != config.output_dir) {
+			if std::fs::remove_dir(dir).is_err() {
+				break;
+			}
+		}
+	}
+
+	std::fs::create_dir_all(&config.cache_dir)?;
+	std::fs::write(&manifest_path, serde_json::to_vec(&outputs)?).into_error_result_with(|| manifest_path.to_string_lossy())?;
+
+	Ok(removed)
+}
+
+#[cfg(test)]
+mod tests {
+	use super::*;
+
+	fn src(path: &str) -> FileSource {
+		FileSource::Src(path.into())
+	}
+
+	#[test]
+	fn collisions() {
+		let (a, b, lua) = (src("a.md"), src("a.html"), FileSource::Lua);
+
+		let result = check_outputs([ (RelativePath::new("a.html"), &a), (RelativePath::new("b.html"), &lua), (RelativePath::new("./a.html"), &b) ]);
+		assert!(matches!(&result, Err(Error::JakeError(JakeError::OutputCollision(output, sources)))
+			if output.as_str() == "a.html" && sources.iter().map(|s| s.as_option().map(|p| p.as_str())).eq([ Some("a.md"), Some("a.html") ])), "Expected a collision, got {result:?}");
+
+		assert!(check_outputs([ (RelativePath::new("a.html"), &a), (RelativePath::new("b/a.html"), &b) ]).is_ok());
+	}
+
+	#[test]
+	fn outside_output_dir() {
+		let result = check_outputs([ (RelativePath::new("a/../../a.html"), &src("a.md")) ]);
+		assert!(matches!(result, Err(Error::JakeError(JakeError::OutputOutsideDir(..)))), "Expected an error, got {result:?}");
+	}
+
+	#[test]
+	fn stale_outputs() {
+		let project_dir = std::env::temp_dir().join(format!("jake-outputs-{}", std::process::id()));
+		let mut config = JakeConfig::test(&project_dir);
+		config.keep_files = [ "kept.html", "assets" ].into_iter().map(|glob| crate::config::build_glob(glob).expect("Glob is valid")).collect();
+
+		let outputs = |paths: &[&str]| paths.iter().map(|&path| RelativePathBuf::from(path)).collect::<BTreeSet<_>>();
+		let write = |path: &str| {
+			let path = RelativePath::new(path).to_logical_path(&config.output_dir);
+			std::fs::create_dir_all(path.parent().expect("Has a parent")).expect("Output dir is created");
+			std::fs::write(path, "").expect("Output is written");
+		};
+
+		let first = [ "index.html", "kept.html", "assets/app.js", "blog/a/index.html", "blog/b.html" ];
+		for path in first.into_iter().chain([ "other.txt" ]) {
+			write(path);
+		}
+
+		assert_eq!(remove_stale(&config, outputs(&first)).expect("First build succeeds"), 0);
+		let removed = remove_stale(&config, outputs(&[ "index.html", "blog/b.html" ])).expect("Second build succeeds");
+
+		let exists = |path: &str| RelativePath::new(path).to_logical_path(&config.output_dir).exists();
+		let state = [ "index.html", "kept.html", "assets/app.js", "blog/a", "blog/b.html", "other.txt" ].map(exists);
+
+		let _ = std::fs::remove_dir_all(&project_dir);
+
+		assert_eq!(removed, 1);
+		assert_eq!(state, [ true, true, true, false, true, true ]);
+	}
+}
@@ -0,0 +1,181 @@
+use crate::{collections, error::{Error, JakeError, ResultExtensions}, frontmatter::FrontMatter, JakeConfig};
+use liquid::model::{Value, ValueView};
+use relative_path::{RelativePath, RelativePathBuf};
+
+/// The front matter key that turns a page into a paginated list.
+pub const PAGINATE_KEY: &str = "paginate";
+
+const NUM_PLACEHOLDER: &str = ":num";
+
+/// The `paginate` front matter of a page.
+#[derive(Debug, Clone, serde::Deserialize)]
+struct Paginate {
+	/// The `site` listing to paginate, e.g. `posts` or `pages`.
+	collection: String,
+	#[serde(default = "default_per_page")]
+	per_page: usize,
+	/// Where pages after the first are written, with `:num` standing in for the page number.
+	/// Relative to the output directory; a trailing `/` writes an `index.html`.
+	/// Defaults to `page/:num/` next to the first page.
+	permalink: Option<String>,
+}
+
+fn default_per_page() -> usize { 10 }
+
+/// The `site` listing a page paginates over, if it's paginated.
+pub fn listing(front_matter: &FrontMatter) -> Option<&str> {
+	front_matter.get(PAGINATE_KEY)?.get("collection")?.as_str()
+}
+
+/// Fans a page with `paginate` front matter out into one output per page of items.
+///
+/// The first page keeps the file's own output.
+/// Returns `None` if the page isn't paginated.
+pub fn paginate(
+	config: &JakeConfig,
+	output: &RelativePath,
+	front_matter: &FrontMatter,
+	site: &liquid::Object,
+) -> Result<Option<Vec<(RelativePathBuf, liquid::Object)>>, Error> {
+	let Some(paginate) = front_matter.get(PAGINATE_KEY) else {
+		return Ok(None);
+	};
+
+	let Paginate { collection, per_page, permalink } = serde_json::from_value(paginate.clone())
+		.into_error_result_with(PAGINATE_KEY)?;
+
+	if per_page == 0 {
+		return Err(JakeError::InvalidFrontMatter("paginate.per_page", String::from("must be at least 1")).into());
+	}
+
+	let items = site.get(collection.as_str())
+		.and_then(|v| v.as_array())
+		.ok_or_else(|| JakeError::InvalidFrontMatter("paginate.collection", format!("'{collection}' is not a collection")))?;
+
+	let items: Vec<_> = items.values().map(|v| v.to_value()).collect();
+	let total_pages = items.len().div_ceil(per_page).max(1);
+
+	let permalink = permalink.unwrap_or_else(|| {
+		let dir = output.parent().map_or(String::new(), |p| p.to_string());
+		format!("{dir}/page/{NUM_PLACEHOLDER}/")
+	});
+
+	let output_of = |page: usize| {
+		if page == 1 {
+			return output.to_owned();
+		}
+
+		let path = permalink.replace(NUM_PLACEHOLDER, &page.to_string());
+		let path = RelativePathBuf::from(path.trim_start_matches('/'));
+
+		if permalink.ends_with('/') { path.join("index.html") } else { path }
+	};
+
+	let url_of = |page: usize| Value::scalar(collections::url_for(config, &output_of(page)));
+
+	let pages = (1..=total_pages).map(|page| {
+		let start = (page - 1) * per_page;
+		let page_items = items.iter().skip(start).take(per_page).cloned().collect();
+
+		let optional = |cond: bool, value: Value| if cond { value } else { Value::Nil };
+
+		let mut paginator = liquid::Object::new();
+		paginator.insert("page".into(), Value::scalar(page as i64));
+		paginator.insert("per_page".into(), Value::scalar(per_page as i64));
+		paginator.insert("total_pages".into(), Value::scalar(total_pages as i64));
+		paginator.insert("total_items".into(), Value::scalar(items.len() as i64));
+		paginator.insert("items".into(), Value::Array(page_items));
+		paginator.insert("url".into(), url_of(page));
+		paginator.insert("previous_page".into(), optional(page > 1, Value::scalar(page as i64 - 1)));
+		paginator.insert("previous_page_url".into(), optional(page > 1, url_of(page.saturating_sub(1))));
+		paginator.insert("next_page".into(), optional(page < total_pages, Value::scalar(page as i64 + 1)));
+		paginator.insert("next_page_url".into(), optional(page < total_pages, url_of(page + 1)));
+
+		(output_of(page), paginator)
+	}).collect();
+
+	Ok(Some(pages))
+}
+
+#[cfg(test)]
+mod tests {
+	use super::*;
+	use serde_json::json;
+
+	fn site(items: usize) -> liquid::Object {
+		let mut site = liquid::Object::new();
+		site.insert("posts".into(), Value::Array((0..items).map(|i| Value::scalar(i as i64)).collect()));
+		site
+	}
+
+	fn paginated(paginate: serde_json::Value, items: usize) -> Result<Option<Vec<(RelativePathBuf, liquid::Object)>>, Error> {
+		let front_matter = FrontMatter::from([ (PAGINATE_KEY.into(), paginate) ]);
+		super::paginate(&JakeConfig::test("/project"), RelativePath::new("blog/index.html"), &front_matter, &site(items))
+	}
+
+	fn field<'a>(paginator: &'a liquid::Object, key: &str) -> &'a Value {
+		paginator.get(key).expect("Paginators have every field")
+	}
+
+	#[test]
+	fn pages() {
+		let pages = paginated(json!({ "collection": "posts", "per_page": 2 }), 5).expect("Valid pagination").expect("Page is paginated");
+
+		let outputs: Vec<_> = pages.iter().map(|(output, _)| output.as_str()).collect();
+		assert_eq!(outputs, [ "blog/index.html", "blog/page/2/index.html", "blog/page/3/index.html" ]);
+
+		for (_, paginator) in &pages {
+			assert_eq!(field(paginator, "total_pages"), &Value::scalar(3));
+			assert_eq!(field(paginator, "total_items"), &Value::scalar(5));
+		}
+
+		let (_, first) = &pages[0];
+		assert_eq!(field(first, "items"), &Value::Array(vec![ Value::scalar(0), Value::scalar(1) ]));
+		assert_eq!(field(first, "url"), &Value::scalar("/blog/"));
+		assert_eq!(field(first, "previous_page"), &Value::Nil);
+		assert_eq!(field(first, "previous_page_url"), &Value::Nil);
+		assert_eq!(field(first, "next_page"), &Value::scalar(2));
+		assert_eq!(field(first, "next_page_url"), &Value::scalar("/blog/page/2/"));
+
+		let (_, middle) = &pages[1];
+		assert_eq!(field(middle, "previous_page_url"), &Value::scalar("/blog/"));
+		assert_eq!(field(middle, "next_page_url"), &Value::scalar("/blog/page/3/"));
+
+		// The last page only holds what's left.
+		let (_, last) = &pages[2];
+		assert_eq!(field(last, "items"), &Value::Array(vec![ Value::scalar(4) ]));
+		assert_eq!(field(last, "previous_page"), &Value::scalar(2));
+		assert_eq!(field(last, "next_page"), &Value::Nil);
+		assert_eq!(field(last, "next_page_url"), &Value::Nil);
+	}
+
+	#[test]
+	fn empty_collection() {
+		let pages = paginated(json!({ "collection": "posts" }), 0).expect("Valid pagination").expect("Page is paginated");
+
+		// An empty listing still renders its first page.
+		assert_eq!(pages.len(), 1);
+		let (output, paginator) = &pages[0];
+		assert_eq!(output.as_str(), "blog/index.html");
+		assert_eq!(field(paginator, "items"), &Value::Array(Vec::new()));
+		assert_eq!(field(paginator, "total_pages"), &Value::scalar(1));
+		assert_eq!(field(paginator, "next_page_url"), &Value::Nil);
+	}
+
+	#[test]
+	fn permalinks() {
+		let pages = paginated(json!({ "collection": "posts", "per_page": 1, "permalink": "/archive-:num.html" }), 2)
+			.expect("Valid pagination").expect("Page is paginated");
+
+		let outputs: Vec<_> = pages.iter().map(|(output, _)| output.as_str()).collect();
+		assert_eq!(outputs, [ "blog/index.html", "archive-2.html" ]);
+	}
+
+	#[test]
+	fn invalid() {
+		assert!(paginated(json!({ "collection": "posts", "per_page": 0 }), 1).is_err());
+		assert!(paginated(json!({ "collection": "missing" }), 1).is_err());
+		assert!(super::paginate(&JakeConfig::test("/project"), RelativePath::new("index.html"), &FrontMatter::new(), &site(1))
+			.expect("Not paginated").is_none());
+	}
+}
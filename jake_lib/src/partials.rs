@@ -0,0 +1,175 @@
+use crate::{error::{Error, ErrorExtensions, JakeError, ResultExtensions}, frontmatter, incremental, JakeConfig};
+use liquid_core::{
+	partials::{PartialCompiler, PartialSource},
+	runtime::PartialStore,
+	Language,
+	Renderable,
+};
+use relative_path::RelativePath;
+use std::{borrow::Cow, collections::BTreeMap, path::PathBuf, sync::Arc};
+
+/// Partial templates for `include` and `render`, keyed by their path relative to the includes directory.
+#[derive(Debug, Default)]
+pub struct JakePartials {
+	dir: PathBuf,
+	sources: BTreeMap<String, String>,
+}
+
+impl JakePartials {
+	pub fn collect(config: &JakeConfig) -> Result<Self, Error> {
+		let JakeConfig { includes_dir, .. } = config;
+
+		let mut sources = BTreeMap::new();
+
+		if !includes_dir.exists() {
+			return Ok(Self { dir: includes_dir.clone(), sources });
+		}
+
+		for entry in crate::walk_files(includes_dir) {
+			let (entry, rel_path) = entry?;
+
+			// Partials may carry front matter like any other template, but it has no meaning here.
+			let (_, content) = frontmatter::file_frontmatter_content(entry.path())
+				.into_error_result_with(|| rel_path.as_str())?
+				.ok_or(JakeError::FileNotUtf8(rel_path.clone()))?;
+
+			sources.insert(rel_path.into_string(), content);
+		}
+
+		Ok(Self { dir: includes_dir.clone(), sources })
+	}
+}
+
+impl PartialSource for JakePartials {
+	fn contains(&self, name: &str) -> bool {
+		self.sources.contains_key(name)
+	}
+
+	fn names(&self) -> Vec<&str> {
+		self.sources.keys().map(String::as_str).collect()
+	}
+
+	fn try_get<'a>(&'a self, name: &str) -> Option<Cow<'a, str>> {
+		self.sources.get(name).map(|s| Cow::Borrowed(s.as_str()))
+	}
+}
+
+impl PartialCompiler for JakePartials {
+	fn compile(self, language: Arc<Language>) -> liquid_core::Result<Box<dyn PartialStore + Send + Sync>> {
+		let mut partials = BTreeMap::new();
+
+		for (name, source) in self.sources {
+			let template = liquid_core::parser::parse(&source, &language)
+				.map(liquid_core::runtime::Template::new)
+				.map_err(|e| e.into_error_with(name.as_str()).into_liquid_error())?;
+
+			partials.insert(name, Arc::new(template) as Arc<dyn Renderable>);
+		}
+
+		Ok(Box::new(JakePartialStore { dir: self.dir, partials }))
+	}
+
+	fn source(&self) -> &dyn PartialSource {
+		self
+	}
+}
+
+struct JakePartialStore {
+	dir: PathBuf,
+	partials: BTreeMap<String, Arc<dyn Renderable>>,
+}
+
+impl JakePartialStore {
+	/// Looks up a partial by its full name, falling back to a match without the extension.
+	/// `{% render 'card' %}` finds `card.html`, or whichever comes first by name if there are several.
+	fn find(&self, name: &str) -> Option<(&str, &Arc<dyn Renderable>)> {
+		self.partials.get_key_value(name).or_else(|| self.partials.iter()
+			.find(|(key, _)| RelativePath::new(key).with_extension("") == RelativePath::new(name)))
+			.map(|(key, partial)| (key.as_str(), partial))
+	}
+}
+
+impl PartialStore for JakePartialStore {
+	fn contains(&self, name: &str) -> bool {
+		self.find(name).is_some()
+	}
+
+	fn names(&self) -> Vec<&str> {
+		self.partials.keys().map(String::as_str).collect()
+	}
+
+	fn try_get(&self, name: &str) -> Option<Arc<dyn Renderable>> {
+		let found = self.find(name);
+
+		// Partials that don't exist yet are recorded too, so creating one rebuilds what looked for it.
+		incremental::record_file(RelativePath::new(found.map_or(name, |(key, _)| key)).to_logical_path(&self.dir));
+
+		found.map(|(_, partial)| partial.clone())
+	}
+
+	fn get(&self, name: &str) -> liquid_core::Result<Arc<dyn Renderable>> {
+		self.try_get(name).ok_or_else(|| JakeError::PartialNotFound(kstring::KString::from_ref(name)).into_liquid_error())
+	}
+}
+
+impl std::fmt::Debug for JakePartialStore {
+	fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+		self.names().fmt(f)
+	}
+}
+
+#[cfg(test)]
+mod tests {
+	use super::*;
+	use crate::{converters::Converters, data_strctures::FileSource, layouts::Layouts};
+	use relative_path::RelativePathBuf;
+
+	fn parser(partials: &[(&str, &str)]) -> liquid::Parser {
+		let sources = partials.iter().map(|&(name, source)| (name.to_owned(), source.to_owned())).collect();
+
+		liquid::ParserBuilder::with_stdlib()
+			.partials(JakePartials { dir: PathBuf::from("/project/_includes"), sources })
+			.build()
+			.expect("Parser builds")
+	}
+
+	fn render(partials: &[(&str, &str)], template: &str) -> Result<String, liquid::Error> {
+		parser(partials).parse(template)?.render(&liquid::object!({}))
+	}
+
+	#[test]
+	fn by_stem() {
+		let partials = [ ("foo.html", "foo"), ("nav/menu.html", "menu {{ x }}") ];
+
+		assert_eq!(render(&partials, "{% include 'foo' %} {% include 'foo.html' %}").expect("Template renders"), "foo foo");
+		assert_eq!(render(&partials, "{% render 'nav/menu', x: 1 %}").expect("Template renders"), "menu 1");
+		assert!(render(&partials, "{% include 'menu' %}").is_err());
+	}
+
+	#[test]
+	fn ambiguous_stems() {
+		let partials = [ ("card.md", "md"), ("card.html", "html") ];
+		assert_eq!(render(&partials, "{% include 'card' %}").expect("Template renders"), "html");
+
+		let partials = [ ("card.html", "html"), ("card", "bare") ];
+		assert_eq!(render(&partials, "{% include 'card' %}").expect("Template renders"), "bare");
+	}
+
+	#[test]
+	fn not_found() {
+		let config = JakeConfig::test(std::env::temp_dir().join(format!("jake-partials-{}", std::process::id())));
+		let parser = parser(&[]);
+		let layouts = Layouts::collect(&config, &parser, &Converters::with_builtins(&config)).expect("Layouts collect");
+
+		let partials = crate::TemplateMirror::of(&parser.parse("").expect("Template parses")).partials.clone().expect("Parser has partials");
+		let runtime = liquid_core::runtime::RuntimeBuilder::new().set_partials(partials.as_ref()).build();
+		let template = parser.parse("{% include 'missing' %}").expect("Template parses");
+
+		let result = crate::parse_content(&config, &layouts, &template, FileSource::Src(RelativePathBuf::from("posts/a.html")), &runtime, &mlua::Lua::new(), None, &[]);
+		let error = result.expect_err("The partial is missing");
+		let (contexts, inner) = error.context_chain();
+
+		assert_eq!(contexts, [ "posts/a.html" ]);
+		assert!(inner.to_string().contains("Partial not found: 'missing'"), "Expected a missing partial, got {inner}");
+	}
+}
@@ -0,0 +1,167 @@
+use crate::{collections, data_strctures::FileSource, error::{Error, JakeError}, frontmatter::FrontMatter, JakeConfig};
+use relative_path::{RelativePath, RelativePathBuf};
+
+/// The front matter key giving a page its own output path.
+pub const PERMALINK_KEY: &str = "permalink";
+
+const CATEGORIES_KEY: &str = "categories";
+const TITLE_KEY: &str = "title";
+
+/// Works out where a file is written according to its permalink, if it has one.
+///
+/// A `permalink` in the front matter always applies.
+/// Otherwise HTML pages still at their default output, i.e. not moved from Lua,
+/// use their collection's permalink or the site-wide one from `jake.yml`.
+///
+/// Permalinks are relative to the output directory, and may use the placeholders
+/// `:year`, `:month`, `:day`, `:slug`, `:title`, `:categories`, `:collection` and `:path`.
+/// A trailing `/` makes a pretty URL, written as an `index.html` in that directory.
+pub fn resolve(
+	config: &JakeConfig,
+	source: &FileSource,
+	output: &RelativePath,
+	front_matter: &FrontMatter,
+	is_default_output: bool,
+) -> Result<Option<RelativePathBuf>, Error> {
+	let collection = collections::collection_of(config, source, front_matter);
+
+	let pattern = match front_matter.get(PERMALINK_KEY) {
+		Some(serde_json::Value::String(pattern)) => pattern.as_str(),
+		Some(serde_json::Value::Null) | None => {
+			let is_page = matches!(output.extension(), Some("html" | "htm"));
+			let default = collection
+				.and_then(|c| config.collections.get(c))
+				.and_then(|c| c.permalink.as_deref())
+				.or(config.permalink.as_deref());
+
+			match default {
+				Some(pattern) if is_page && is_default_output => pattern,
+				_ => return Ok(None),
+			}
+		},
+		Some(other) => return Err(JakeError::InvalidFrontMatter(PERMALINK_KEY, format!("expected a string, got '{other}'")).into()),
+	};
+
+	let path = source.as_option().map_or(output, |s| s.as_relative_path());
+	let (date, slug) = collections::date_and_slug(collection, front_matter, path);
+
+	let title = match front_matter.get(TITLE_KEY) {
+		Some(serde_json::Value::String(title)) => slugify(title),
+		_ => slug.to_owned(),
+	};
+
+	let categories = match front_matter.get(CATEGORIES_KEY) {
+		Some(serde_json::Value::String(categories)) => categories.split_whitespace().map(slugify).collect::<Vec<_>>().join("/"),
+		Some(serde_json::Value::Array(categories)) => categories.iter()
+			.filter_map(|c| c.as_str())
+			.map(slugify)
+			.collect::<Vec<_>>()
+			.join("/"),
+		_ => String::new(),
+	};
+
+	let dir = path.parent().map_or(String::new(), |p| p.to_string());
+
+	// Longer placeholders go first so none is cut short by another it starts with.
+	let placeholders = [
+		(":categories", categories),
+		(":collection", collection.map_or(String::new(), |c| c.to_string())),
+		(":month", date.map_or(String::new(), |d| format!("{:02}", d.month()))),
+		(":title", title),
+		(":year", date.map_or(String::new(), |d| d.year().to_string())),
+		(":path", dir),
+		(":slug", slug.to_owned()),
+		(":day", date.map_or(String::new(), |d| format!("{:02}", d.day()))),
+	];
+
+	let resolved = placeholders.iter().fold(pattern.to_owned(), |pattern, (placeholder, value)| pattern.replace(placeholder, value));
+
+	// Empty placeholders leave empty segments behind.
+	let mut segments: Vec<_> = resolved.split('/').filter(|s| !s.is_empty()).collect();
+
+	if resolved.ends_with('/') {
+		// `/:path/:slug/` shouldn't turn `blog/index.md` into `blog/index/index.html`.
+		if segments.last() == Some(&"index") {
+			segments.pop();
+		}
+
+		segments.push("index.html");
+	}
+
+	if segments.is_empty() {
+		return Err(JakeError::InvalidFrontMatter(PERMALINK_KEY, format!("'{pattern}' resolves to an empty path")).into());
+	}
+
+	Ok(Some(RelativePathBuf::from(segments.join("/"))))
+}
+
+/// Lowercases text and joins its words with `-`, for use in paths.
+fn slugify(text: &str) -> String {
+	text.split(|c: char| !c.is_alphanumeric())
+		.filter(|word| !word.is_empty())
+		.map(str::to_lowercase)
+		.collect::<Vec<_>>()
+		.join("-")
+}
+
+#[cfg(test)]
+mod tests {
+	use super::*;
+	use serde_json::json;
+
+	fn resolved(source: &str, permalink: serde_json::Value, extra: &[(&'static str, serde_json::Value)]) -> Result<Option<RelativePathBuf>, Error> {
+		let front_matter = [ (PERMALINK_KEY, permalink) ].into_iter().chain(extra.iter().cloned())
+			.map(|(key, value)| (key.into(), value))
+			.collect();
+		let output = RelativePath::new(source).with_extension("html");
+
+		resolve(&JakeConfig::test("/project"), &FileSource::Src(source.into()), &output, &front_matter, true)
+	}
+
+	fn path(source: &str, permalink: &str) -> String {
+		resolved(source, json!(permalink), &[]).expect("Permalink is valid").expect("Permalink applies").into_string()
+	}
+
+	#[test]
+	fn slugs() {
+		assert_eq!(slugify("Hello, World!"), "hello-world");
+		assert_eq!(slugify("Ünïcödé Straße"), "ünïcödé-straße");
+		assert_eq!(slugify("日本語 テキスト"), "日本語-テキスト");
+		assert_eq!(slugify("  a -- b__c  "), "a-b-c");
+		assert_eq!(slugify("?!"), "");
+	}
+
+	#[test]
+	fn placeholders() {
+		assert_eq!(path("blog/my-post.md", "/:path/:slug.html"), "blog/my-post.html");
+		assert_eq!(path("blog/my-post.md", "/:path/:slug/"), "blog/my-post/index.html");
+		assert_eq!(path("blog/index.md", "/:path/:slug/"), "blog/index.html");
+
+		let titled = resolved("blog/my-post.md", json!("/:title/"), &[ ("title", json!("Déjà Vu, Again")) ]);
+		assert_eq!(titled.expect("Permalink is valid").expect("Permalink applies").as_str(), "déjà-vu-again/index.html");
+
+		let categorised = resolved("my-post.md", json!("/:categories/:slug/"), &[ ("categories", json!([ "Rust Lang", "Web" ])) ]);
+		assert_eq!(categorised.expect("Permalink is valid").expect("Permalink applies").as_str(), "rust-lang/web/my-post/index.html");
+	}
+
+	#[test]
+	fn separators() {
+		// Empty placeholders and doubled slashes don't leave empty segments.
+		assert_eq!(path("my-post.md", "//:categories//:slug"), "my-post");
+		assert_eq!(path("my-post.md", "/:year/:month/:slug/"), "my-post/index.html");
+		assert_eq!(path("my-post.md", "/about//"), "about/index.html");
+	}
+
+	#[test]
+	fn unknown_placeholders() {
+		// Anything that isn't a placeholder is kept as written.
+		assert_eq!(path("my-post.md", "/:author/:slug.html"), ":author/my-post.html");
+	}
+
+	#[test]
+	fn invalid() {
+		assert!(resolved("my-post.md", json!(3), &[]).is_err());
+		assert!(resolved("my-post.md", json!(":categories"), &[]).is_err());
+		assert!(resolved("my-post.md", json!(null), &[]).expect("No permalink is valid").is_none());
+	}
+}
@@ -18,27 +18,67 @@ pub enum JakeCommand {
 	/// Build a Jake site.
 	/// 
 	/// This command processes a Jake project and writes the output to the target directory.
-	Build,
+	Build {
+		#[arg(short, long)]
+		/// Only re-render outputs whose sources, layouts, includes, data or globals changed since the last build.
+		/// Changes to 'jake.yml' or the plugins always rebuild everything.
+		incremental: bool,
+
+		#[arg(long)]
+		/// Build pages marked as drafts too.
+		drafts: bool,
+	},
 
 	/// Serve a Jake site.
 	/// 
 	/// This command processes a Jake project and writes the output to the target directory.  
 	/// It then serves the site as a local web server. The web server can be connected to locally
 	/// and supports hot reloading.
-	Serve {
-		#[arg(env="JAKE_PORT")]
-		/// The port to serve the site on.
-		/// The default is 4000.
-		port: Option<u16>,
-	},
+	Serve(ServeArgs),
 
 	/// Cleans temporary data of a Jake project.
 	/// 
 	/// This command removes all 'temporary' data generated by Jake.
-	/// This includes the output directory.
+	/// This includes the output directory and the cache directory.
 	Clean,
 }
 
+#[derive(Debug, Clone, clap::Args)]
+pub struct ServeArgs {
+	#[arg(env="JAKE_PORT")]
+	/// The port to serve the site on.
+	/// The default is 4000.
+	pub port: Option<u16>,
+
+	#[arg(long, env="JAKE_HOST", default_value = "0.0.0.0")]
+	/// The address to listen on.
+	/// Use 127.0.0.1 to only accept connections from this machine.
+	pub host: String,
+
+	#[arg(long, env="JAKE_BASE_PATH")]
+	/// The path to serve the site under, such as '/my-project' for a GitHub Pages project site.
+	/// Defaults to the 'base_url' from 'jake.yml'.
+	pub base_path: Option<String>,
+
+	#[arg(short, long)]
+	/// Open the site in the browser once it's being served.
+	pub open: bool,
+
+	#[arg(long)]
+	/// Serve pages marked as drafts too.
+	/// Can be toggled while serving with 'drafts on' and 'drafts off'.
+	pub drafts: bool,
+
+	#[arg(short, long)]
+	/// Render pages in memory as they're asked for, instead of building the site to the output directory.
+	/// Serving starts right away, and the output directory is left alone.
+	pub memory: bool,
+
+	#[arg(long, env="JAKE_DEBOUNCE", default_value_t = 100)]
+	/// How long to wait for changes to settle before rebuilding, in milliseconds.
+	pub debounce: u64,
+}
+
 #[derive(Debug, Clone, clap::Args)]
 pub struct JakePathArgs {
 	#[arg(short, long, env="JAKE_DIR")]
@@ -0,0 +1,89 @@
+//! The commands `serve` takes from the terminal.
+
+#[derive(Debug, Clone, clap::Parser)]
+#[command(multicall = true, disable_help_flag = true, help_template = "Commands:\n{subcommands}")]
+/// A line typed into the terminal while serving.
+struct ConsoleLine {
+	#[clap(subcommand)]
+	command: Command,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, clap::Subcommand)]
+pub enum Command {
+	/// Stop rebuilding when files change.
+	Pause,
+	/// Rebuild when files change again.
+	Start,
+	/// Reload the site from scratch, running the plugins again.
+	Refresh,
+	/// Delete the output and cache directories, then build the site again.
+	Clean,
+	/// Open the site in the browser.
+	Open,
+	/// Show what the last build did.
+	Stats,
+	/// Serve pages marked as drafts, or stop serving them.
+	Drafts {
+		#[arg(value_enum)]
+		state: Toggle,
+	},
+	/// Stop serving and exit.
+	#[command(alias = "exit")]
+	Quit,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, clap::ValueEnum)]
+pub enum Toggle {
+	On,
+	Off,
+}
+
+impl From<Toggle> for bool {
+	fn from(toggle: Toggle) -> Self {
+		toggle == Toggle::On
+	}
+}
+
+/// Parses a line of input. Blank lines are no command at all.
+///
+/// Asking for `help` is an error too, with the help as its message.
+pub fn parse(line: &str) -> Result<Option<Command>, clap::Error> {
+	let words: Vec<_> = line.split_whitespace().collect();
+	if words.is_empty() {
+		return Ok(None);
+	}
+
+	<ConsoleLine as clap::Parser>::try_parse_from(words).map(|line| Some(line.command))
+}
+
+/// Reads commands from stdin until it closes, handing them to `send`.
+///
+/// Reading blocks, so it gets a thread of its own rather than a spot on the runtime.
+pub fn spawn(send: impl Fn(Command) + Send + 'static) -> std::thread::JoinHandle<()> {
+	std::thread::spawn(move || {
+		for line in std::io::stdin().lines() {
+			let Ok(line) = line else { break };
+
+			match parse(&line) {
+				Ok(Some(command)) => send(command),
+				Ok(None) => (),
+				Err(e) => { let _ = e.print(); },
+			}
+		}
+	})
+}
+
+#[cfg(test)]
+mod tests {
+	use super::*;
+
+	#[test]
+	pub fn verify_console() {
+		use clap::CommandFactory;
+		ConsoleLine::command().debug_assert();
+
+		assert_eq!(parse("  drafts on ").unwrap(), Some(Command::Drafts { state: Toggle::On }));
+		assert_eq!(parse("").unwrap(), None);
+		assert!(parse("drafts maybe").is_err());
+	}
+}
@@ -2,12 +2,14 @@
 #![feature(let_chains)]
 
 mod cli;
+mod console;
+mod overlay;
+mod server;
 
-use std::sync::LazyLock;
+use std::{path::PathBuf, sync::LazyLock};
 
 use cli::CliArgs;
-use iter_tools::Itertools;
-use jake_lib::{error::ResultExtensions, JakeConfig};
+use jake_lib::{error::ResultExtensions, BuildOptions, JakeConfig};
 use notify::Watcher;
 
 pub static ARGS: LazyLock<CliArgs> = LazyLock::new(<CliArgs as clap::Parser>::parse);
@@ -17,53 +19,125 @@ fn main() {
 
 	match &ARGS.command {
 		Completion { shell } => cli::generate_completion(*shell),
-		Build => jake_lib::process_project(&init_config()).handle_as_error(),
-		Serve { port } => serve(init_config(), port.unwrap_or(4000)),
+		Build { incremental, drafts } => jake_lib::process_project(&init_config(), &BuildOptions { incremental: *incremental, drafts: *drafts }).handle_as_error(),
+		Serve(args) => serve(init_config(), args),
 		Clean => {
-			let JakeConfig { output_dir, .. } = init_config();
-			if output_dir.exists() {
-				std::fs::remove_dir_all(&output_dir).handle_as_error();
+			let JakeConfig { output_dir, cache_dir, .. } = init_config();
+			for dir in [ output_dir, cache_dir ] {
+				if dir.exists() {
+					std::fs::remove_dir_all(&dir).handle_as_error();
+				}
 			}
 		}
 	}
 }
 
 fn init_config() -> JakeConfig {
-	let paths = &ARGS.path_args;
-
-	let project_dir = paths.dir.as_ref().map_or(std::env::current_dir().unwrap(), |p| std::env::current_dir().unwrap().join(p));
-
 	// Check for the 'jake.yml' file.
-	if !project_dir.join("jake.yml").exists() {
+	if !project_dir().join("jake.yml").exists() {
 		eprintln!("No 'jake.yml' file found in the project directory.");
 		std::process::exit(1);
 	}
 
-	jake_lib::JakeConfig {
-		source_dir: project_dir.join("src"),
-		output_dir: paths.out.to_owned().unwrap_or(project_dir.join("site")),
-		plugins_dir: project_dir.join("plugins"),
-		layout_dir: project_dir.join("layouts"),
-		project_dir,
+	match load_config() {
+		Ok(config) => config,
+		Err(e) => {
+			e.print_error();
+			std::process::exit(1);
+		}
 	}
 }
 
-fn serve(config: JakeConfig, port: u16) {
+fn project_dir() -> PathBuf {
+	let current_dir = std::env::current_dir().unwrap();
+	ARGS.path_args.dir.as_ref().map_or(current_dir.clone(), |p| current_dir.join(p))
+}
+
+fn load_config() -> Result<JakeConfig, jake_lib::error::Error> {
+	let current_dir = std::env::current_dir().unwrap();
+	JakeConfig::load(project_dir(), ARGS.path_args.out.as_ref().map(|p| current_dir.join(p)))
+}
+
+/// What `serve` watches: `jake.yml`, through the project directory, and every input directory there is.
+fn watched_paths(config: &JakeConfig) -> Vec<(PathBuf, notify::RecursiveMode)> {
+	let JakeConfig { project_dir, source_dir, layout_dir, plugins_dir, includes_dir, data_dir, sass_load_paths, .. } = config;
+
+	let dirs = [ source_dir, layout_dir, plugins_dir, includes_dir, data_dir ].into_iter()
+		.chain(sass_load_paths)
+		.filter(|dir| dir.is_dir())
+		.map(|dir| (dir.clone(), notify::RecursiveMode::Recursive));
+
+	std::iter::once((project_dir.clone(), notify::RecursiveMode::NonRecursive)).chain(dirs).collect()
+}
+
+fn serve(mut config: JakeConfig, args: &cli::ServeArgs) {
 	fn msg(msg: &str, err: bool) {
 		let col = if err { "\x1b[31m" } else { "\x1b[32m" };
 		println!("{col}{msg}\x1b[0m");
 	}
 
-	jake_lib::process_project(&config).handle_as_error();
+	// Serving rebuilds only what changed, starting from whatever the last build left behind.
+	let mut options = BuildOptions { incremental: true, drafts: args.drafts };
+
+	// A site that loaded but failed to build is kept, so the next change can still rebuild just what it affects.
+	// Sites served from memory aren't built at all, they render pages as they're asked for.
+	let load_site = |config: &JakeConfig, options: &BuildOptions| match jake_lib::Site::new(config.clone(), options.clone()) {
+		Ok(mut site) => {
+			let result = if args.memory { Ok(()) } else { site.build() };
+			(Some(site), result)
+		},
+		Err(e) => (None, Err(e)),
+	};
+
+	let build_error = overlay::BuildError::default();
+
+	// Errors go to the terminal and to the browser, until a build succeeds.
+	let report = |result: Result<(), jake_lib::error::Error>| {
+		let overlay = result.as_ref().err().map(overlay::render);
+		let failed = overlay.is_some();
+
+		if let Ok(mut error) = build_error.write() {
+			*error = overlay;
+		}
+
+		result.handle_as_error();
+		!failed
+	};
+
+	let started = std::time::Instant::now();
+	let (site, result) = load_site(&config, &options);
+	let site = server::SharedSite::new(std::sync::RwLock::new(site));
+	report(result);
+
+	// When the last build finished, and how long it took.
+	let mut last_build = (std::time::Instant::now(), started.elapsed());
+
+	let mut filter = match config.watch_filter() {
+		Ok(filter) => filter,
+		Err(e) => return e.print_error(),
+	};
 
 	eprintln!();
 
 	let (tx, rx) = std::sync::mpsc::channel();
 	let tx_clone = tx.clone();
 	let mut watcher = notify::recommended_watcher(move |ev| tx_clone.send(Msg::Watcher(ev)).unwrap()).unwrap();
-	watcher.watch(&config.source_dir, notify::RecursiveMode::Recursive).unwrap();
-	watcher.watch(&config.layout_dir, notify::RecursiveMode::Recursive).unwrap();
-	watcher.watch(&config.plugins_dir, notify::RecursiveMode::Recursive).unwrap();
+
+	fn watch(watcher: &mut impl Watcher, watched: &[(PathBuf, notify::RecursiveMode)], paths: Vec<(PathBuf, notify::RecursiveMode)>) -> Vec<(PathBuf, notify::RecursiveMode)> {
+		for (path, _) in watched {
+			let _ = watcher.unwatch(path);
+		}
+
+		for (path, mode) in &paths {
+			if let Err(e) = watcher.watch(path, *mode) {
+				msg(&format!("Couldn't watch '{}': {e}", path.display()), true);
+			}
+		}
+
+		paths
+	}
+
+	let mut watched = watch(&mut watcher, &[], watched_paths(&config));
 
 	let runtime = tokio::runtime::Builder::new_multi_thread()
 		.enable_all()
@@ -73,117 +147,235 @@ fn serve(config: JakeConfig, port: u16) {
 	let reload_layer = tower_livereload::LiveReloadLayer::new();
 	let reload_handle = reload_layer.reloader();
 
-	let serve_path = config.output_dir.clone();
-	let server = async move || {
-		let app = axum::Router::new()
-			.fallback_service(tower_http::services::ServeDir::new(serve_path))
-			.layer(reload_layer)
-			;
+	let port = args.port.unwrap_or(4000);
+	let debounce = std::time::Duration::from_millis(args.debounce);
+	let base_path = server::SharedBasePath::new(std::sync::RwLock::new(server::base_path(args.base_path.as_deref().unwrap_or(&config.base_url))));
 
-		let listener = tokio::net::TcpListener::bind(("0.0.0.0", port)).await.unwrap();
-		axum::serve(listener, app).await.unwrap();
+	let output_dir = server::SharedDir::new(std::sync::RwLock::new(config.output_dir.clone()));
+	let outputs = match args.memory {
+		true => server::Outputs::Memory(site.clone()),
+		false => server::Outputs::Dir(output_dir.clone()),
 	};
 
-	msg(&format!("Serving site at http://localhost:{}/", port), false);
-	
-	let _handle = runtime.spawn(server());
+	let app = server::router(outputs, base_path.clone())
+		.layer(axum::middleware::from_fn_with_state(build_error.clone(), overlay::inject))
+		.layer(reload_layer);
 
-	fn cause_reload(ev: notify::Result<notify::Event>) -> bool {
-		if let Err(e) = ev {
-			msg(&format!("watch error: {e:?}"), true);
-			return false;
-		}
+	let listener = match runtime.block_on(tokio::net::TcpListener::bind((args.host.as_str(), port))) {
+		Ok(listener) => listener,
+		Err(e) => return msg(&format!("Couldn't listen on {}:{port}: {e}", args.host), true),
+	};
+
+	let shutdown = std::sync::Arc::new(tokio::sync::Notify::new());
+	let server = runtime.spawn({
+		let shutdown = shutdown.clone();
+		async move { axum::serve(listener, app).with_graceful_shutdown(async move { shutdown.notified().await }).await }
+	});
+
+	// Anywhere is reachable from here too.
+	let host = if args.host == "0.0.0.0" { "localhost" } else { args.host.as_str() };
+	let site_url = |base_path: &str| format!("http://{host}:{port}{base_path}/");
+	let mut url = site_url(&base_path.read().map(|base_path| base_path.clone()).unwrap_or_default());
+	msg(&format!("Serving site at {url}"), false);
+
+	if args.open {
+		server::open_browser(&url);
+	}
+
+	fn changed_paths(ev: notify::Result<notify::Event>) -> Vec<std::path::PathBuf> {
+		let ev = match ev {
+			Ok(ev) => ev,
+			Err(e) => {
+				msg(&format!("watch error: {e:?}"), true);
+				return Vec::new();
+			},
+		};
 
-		matches!(
-			ev.unwrap().kind,
+		match ev.kind {
 			notify::EventKind::Create(_)
 			| notify::EventKind::Modify(_)
-			| notify::EventKind::Remove(_)
-		)
+			| notify::EventKind::Remove(_) => ev.paths,
+			_ => Vec::new(),
+		}
 	}
 
 	enum Msg {
 		Watcher(notify::Result<notify::Event>),
-		Stdin(StdInMsg),
-	}
-
-	enum StdInMsg {
-		Pause(bool),
-		Refresh,
+		Stdin(console::Command),
 	}
-	use StdInMsg::*;
-
-	let std_watcher = async move {
-		let stdin = std::io::stdin();
-
-		loop {
-			let mut buf = String::new();
-			stdin.read_line(&mut buf).unwrap();
-			
-			match buf.trim() {
-				"pause" => {
-					msg("\x1b[1APaused", false);
-					tx.send(Msg::Stdin(Pause(true))).unwrap()
-				},
-				"start" => {
-					msg("\x1b[1AResumed", false);
-					tx.send(Msg::Stdin(Pause(false))).unwrap()
-				},
-				"refresh" => {
-					msg("\x1b[1ARefreshing...", false);
-					tx.send(Msg::Stdin(Refresh)).unwrap()
-				},
-				_ => msg(&format!("\x1bUnknown command: {}", buf.trim()), true),
-			}
-
-			buf.clear();
-		}
-	};
 
-	let _std_handle = runtime.spawn(std_watcher);
+	console::spawn(move |command| { let _ = tx.send(Msg::Stdin(command)); });
+	msg("Type 'help' to see the commands", false);
 
 	let mut paused = false;
+	// Changes seen while paused wait for the next rebuild.
+	let mut changed = Vec::new();
 
-	loop {
-		let Ok(res) = rx.recv() else {
+	'serve: loop {
+		let Ok(first) = rx.recv() else {
 			msg("Channel closed unexpectedly", true);
 			break;
 		};
-		std::thread::sleep(std::time::Duration::from_millis(100));
 
-		let (stdins, watches): (Vec<_>, Vec<_>) = rx.try_iter().partition_map(|ev| match ev {
-			Msg::Stdin(msg) => iter_tools::Either::Left(msg),
-			Msg::Watcher(ev) => iter_tools::Either::Right(ev),
-		});
+		// Changes settle first, so saving several files at once rebuilds once.
+		let mut messages = vec![ first ];
+		while let Ok(message) = rx.recv_timeout(debounce) {
+			messages.push(message);
+		}
 
 		let mut refresh = false;
+		let mut clean = false;
+
+		for message in messages {
+			use console::Command::*;
 
-		if let Msg::Stdin(msg) = &res {
-			match msg {
-				Pause(p) => paused = *p,
+			let command = match message {
+				Msg::Watcher(ev) => {
+					changed.extend(changed_paths(ev));
+					continue;
+				},
+				Msg::Stdin(command) => command,
+			};
+
+			match command {
+				Pause => {
+					paused = true;
+					msg("Paused", false);
+				},
+				Start => {
+					paused = false;
+					msg("Resumed", false);
+				},
 				Refresh => refresh = true,
+				Clean => clean = true,
+				Open => server::open_browser(&url),
+				Stats => print_stats(&site, args.memory, last_build, &options),
+				Drafts { state } => {
+					let drafts = bool::from(state);
+					refresh |= drafts != options.drafts;
+					options.drafts = drafts;
+					msg(if drafts { "Serving drafts" } else { "Not serving drafts" }, false);
+				},
+				Quit => break 'serve,
 			}
 		}
 
-		for msg in stdins {
-			match msg {
-				Pause(p) => paused = p,
-				Refresh => refresh = true,
+		// Sites served from memory never touch the output directory.
+		if clean && !args.memory {
+			for dir in [ &config.output_dir, &config.cache_dir ] {
+				if dir.exists() && let Err(e) = std::fs::remove_dir_all(dir) {
+					msg(&format!("Couldn't delete '{}': {e}", dir.display()), true);
+				}
 			}
 		}
+		refresh |= clean;
 
-		let reload = refresh || (!paused && (
-			if let Msg::Watcher(ev) = res { cause_reload(ev) } else { false }
-				|| watches.into_iter().any(cause_reload)
-		));
+		// Input directories made since they were last looked for may already hold files.
+		let paths = watched_paths(&config);
+		if paths != watched {
+			changed.extend(paths.iter().filter(|path| !watched.contains(path)).map(|(dir, _)| dir.clone()));
+			watched = watch(&mut watcher, &watched, paths);
+		}
 
-		if !reload { continue; }
+		// The project directory is only watched for `jake.yml`.
+		let config_file = config.project_dir.join(jake_lib::CONFIG_FILE);
+		changed.retain(|path| *path == config_file || (!filter.is_ignored(path)
+			&& watched.iter().any(|(dir, mode)| *mode == notify::RecursiveMode::Recursive && path.starts_with(dir))));
+
+		if !refresh && (paused || changed.is_empty()) { continue; }
+
+		if changed.contains(&config_file) {
+			match load_config() {
+				Ok(new_config) => {
+					if new_config.output_dir != config.output_dir && let Ok(mut output_dir) = output_dir.write() {
+						output_dir.clone_from(&new_config.output_dir);
+					}
+
+					// A base path given on the command line wins over `base_url`.
+					let new_base_path = server::base_path(args.base_path.as_deref().unwrap_or(&new_config.base_url));
+					if let Ok(mut base_path) = base_path.write() && *base_path != new_base_path {
+						url = site_url(&new_base_path);
+						*base_path = new_base_path;
+						msg(&format!("Serving site at {url}"), false);
+					}
+
+					match new_config.watch_filter() {
+						Ok(new_filter) => filter = new_filter,
+						Err(e) => e.print_error(),
+					}
+
+					config = new_config;
+					refresh = true;
+
+					let paths = watched_paths(&config);
+					if paths != watched {
+						watched = watch(&mut watcher, &watched, paths);
+					}
+				},
+				Err(e) => {
+					msg("Couldn't reload 'jake.yml'", true);
+					e.print_error();
+					changed.clear();
+					continue;
+				},
+			}
+		}
 
 		msg("Reloading site...", false);
-		jake_lib::process_project(&config).handle_as_error();
+		let Ok(mut site) = site.write() else {
+			msg("The site was lost to a panic", true);
+			break;
+		};
+
+		let started = std::time::Instant::now();
+		let result = match &mut *site {
+			// Refreshing starts over, running the plugins again.
+			Some(site) if !refresh && args.memory => site.update(&std::mem::take(&mut changed)),
+			Some(site) if !refresh => site.rebuild(&std::mem::take(&mut changed)),
+			_ => {
+				changed.clear();
+				let result;
+				(*site, result) = load_site(&config, &options);
+				result
+			},
+		};
+		drop(site);
+		last_build = (std::time::Instant::now(), started.elapsed());
+
+		let built = report(result);
 		eprintln!();
-		msg("Site reloaded", false);
+
+		if built {
+			msg("Site reloaded", false);
+		} else {
+			msg("Site failed to build", true);
+		}
 
 		reload_handle.reload();
 	}
+
+	msg("Shutting down...", false);
+	shutdown.notify_one();
+
+	// Live reload keeps requests open, so they only get a moment to finish.
+	let _ = runtime.block_on(async { tokio::time::timeout(std::time::Duration::from_secs(1), server).await });
+	runtime.shutdown_background();
+}
+
+/// Prints what the last build did, for the `stats` command.
+fn print_stats(site: &server::SharedSite, memory: bool, (finished, took): (std::time::Instant, std::time::Duration), options: &BuildOptions) {
+	println!("Last build took {took:.2?}, {}s ago", finished.elapsed().as_secs());
+
+	if let Ok(site) = site.read() && let Some(site) = &*site {
+		if memory {
+			println!("{} outputs rendered in memory since the last change", site.rendered_in_memory());
+		} else {
+			let jake_lib::BuildStats { rendered, skipped, skipped_binaries, removed } = site.stats();
+			println!("{rendered} outputs rendered, {skipped} up to date, {skipped_binaries} binary files already copied, {removed} stale outputs removed");
+		}
+	} else {
+		println!("The site failed to load");
+	}
+
+	println!("Drafts are {}", if options.drafts { "served" } else { "not served" });
 }
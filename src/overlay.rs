@@ -0,0 +1,75 @@
+//! Shows build errors in the browser while serving, over whatever the last good build left.
+
+use axum::{body::Body, extract::{Request, State}, http::{header, StatusCode}, middleware::Next, response::Response};
+use jake_lib::error::Error;
+use std::sync::{Arc, RwLock};
+
+/// The overlay for the last build, if it failed.
+pub type BuildError = Arc<RwLock<Option<String>>>;
+
+/// Renders an error as an overlay to put at the end of a page.
+pub fn render(error: &Error) -> String {
+	let error = error.downcast();
+	let (contexts, error) = error.context_chain();
+
+	let contexts: String = contexts.iter()
+		.map(|context| format!("<li>in {}</li>", escape(context)))
+		.collect();
+
+	format!(r#"<div id="jake-error-overlay" style="position: fixed; inset: 0; z-index: 2147483647; overflow: auto; padding: 2em; background: rgba(20, 20, 20, 0.95); color: #eee; font: 14px/1.5 monospace;">
+	<button onclick="this.parentElement.remove()" style="float: right; font: inherit;">Dismiss</button>
+	<h2 style="color: #ff6b6b; margin-top: 0;">Build failed</h2>
+	<ul style="color: #aaa;">{contexts}</ul>
+	<pre style="white-space: pre-wrap;">{}</pre>
+</div>"#, escape(&error.to_string()))
+}
+
+/// Puts the overlay in HTML responses while the last build is failing.
+/// Missing pages show it too, as a failed first build may have made none.
+pub async fn inject(State(error): State<BuildError>, mut request: Request, next: Next) -> Response {
+	let overlay = error.read().ok().and_then(|e| e.clone());
+
+	// A cached page wouldn't get the overlay.
+	if overlay.is_some() {
+		request.headers_mut().remove(header::IF_NONE_MATCH);
+		request.headers_mut().remove(header::IF_MODIFIED_SINCE);
+	}
+
+	let response = next.run(request).await;
+
+	let Some(overlay) = overlay else {
+		return response;
+	};
+
+	let is_html = response.headers().get(header::CONTENT_TYPE)
+		.and_then(|v| v.to_str().ok())
+		.is_some_and(|v| v.starts_with("text/html"));
+
+	if !is_html && response.status() != StatusCode::NOT_FOUND {
+		return response;
+	}
+
+	let (mut parts, body) = response.into_parts();
+
+	let mut html = match is_html {
+		true => match axum::body::to_bytes(body, usize::MAX).await {
+			Ok(bytes) => String::from_utf8_lossy(&bytes).into_owned(),
+			Err(_) => String::new(),
+		},
+		false => String::from("<!DOCTYPE html><html><body></body></html>"),
+	};
+
+	match html.rfind("</body>") {
+		Some(end) => html.insert_str(end, &overlay),
+		None => html.push_str(&overlay),
+	}
+
+	parts.headers.remove(header::CONTENT_LENGTH);
+	parts.headers.insert(header::CONTENT_TYPE, header::HeaderValue::from_static("text/html; charset=utf-8"));
+
+	Response::from_parts(parts, Body::from(html))
+}
+
+fn escape(text: &str) -> String {
+	text.replace('&', "&amp;").replace('<', "&lt;").replace('>', "&gt;").replace('"', "&quot;")
+}
@@ -0,0 +1,189 @@
+//! The web server behind `serve`.
+
+use axum::{body::Body, extract::{Request, State}, http::{header, StatusCode, Uri}, response::{Html, IntoResponse, Redirect, Response}, Router};
+use relative_path::RelativePath;
+use std::{path::{Path, PathBuf}, sync::{Arc, RwLock}};
+use tower_http::services::{ServeDir, ServeFile};
+
+/// The page served, with a 404 status, for paths with nothing behind them.
+const NOT_FOUND_PAGE: &str = "404.html";
+
+/// Tidies a base path into `/path` form, or nothing for the root.
+pub fn base_path(path: &str) -> String {
+	let path = path.trim_matches('/');
+
+	if path.is_empty() { String::new() } else { format!("/{path}") }
+}
+
+/// The site as `serve` keeps it, shared with the server when serving from memory.
+pub type SharedSite = Arc<RwLock<Option<jake_lib::Site>>>;
+
+/// The output directory as `serve` keeps it, shared with the server so it follows changes to `jake.yml`.
+pub type SharedDir = Arc<RwLock<PathBuf>>;
+
+/// The base path as `serve` keeps it, shared with the server so it follows changes to `base_url`.
+pub type SharedBasePath = Arc<RwLock<String>>;
+
+/// Where served files come from.
+pub enum Outputs {
+	/// The output directory, as builds leave it.
+	Dir(SharedDir),
+	/// The site itself, rendering pages as they're asked for.
+	Memory(SharedSite),
+}
+
+impl Outputs {
+	fn exists(&self, rel_path: &str) -> bool {
+		match self {
+			Self::Dir(dir) => dir.read().is_ok_and(|dir| dir.join(rel_path).is_file()),
+			// Finding out may mean working out the whole site.
+			Self::Memory(site) => tokio::task::block_in_place(|| {
+				let Ok(site) = site.read() else { return false };
+				site.as_ref().is_some_and(|site| site.has_output(RelativePath::new(rel_path)).unwrap_or(false))
+			}),
+		}
+	}
+}
+
+/// Where the site is served from, and under which path.
+struct Site {
+	outputs: Outputs,
+	base_path: SharedBasePath,
+}
+
+/// Serves the site under `base_path`, redirecting the root there.
+pub fn router(outputs: Outputs, base_path: SharedBasePath) -> Router {
+	let router = match &outputs {
+		Outputs::Dir(dir) => Router::new().fallback(serve_dir).with_state(dir.clone()),
+		Outputs::Memory(site) => Router::new().fallback(serve_memory).with_state(site.clone()),
+	};
+
+	let site = Site { outputs, base_path };
+
+	router.layer(axum::middleware::map_request_with_state(Arc::new(site), resolve))
+}
+
+/// Serves a file from the output directory, wherever it is at the time.
+async fn serve_dir(State(dir): State<SharedDir>, request: Request) -> Response {
+	let Ok(dir) = dir.read().map(|dir| dir.clone()) else {
+		return (StatusCode::INTERNAL_SERVER_ERROR, "The output directory was lost to a panic").into_response();
+	};
+
+	match ServeDir::new(&dir).not_found_service(ServeFile::new(dir.join(NOT_FOUND_PAGE))).try_call(request).await {
+		Ok(response) => response.map(Body::new).into_response(),
+		Err(e) => (StatusCode::INTERNAL_SERVER_ERROR, e.to_string()).into_response(),
+	}
+}
+
+/// Serves an output of the site from memory, rendering it if it wasn't yet.
+async fn serve_memory(State(site): State<SharedSite>, uri: Uri) -> Response {
+	let path = uri.path().trim_start_matches('/');
+	let path = if path.is_empty() || path.ends_with('/') { format!("{path}index.html") } else { path.to_owned() };
+
+	memory_output(site, Some(path)).await
+}
+
+/// Serves `path` from memory, or the 404 page if nothing makes it or there's no `path` at all.
+async fn memory_output(site: SharedSite, path: Option<String>) -> Response {
+	let output = tokio::task::spawn_blocking(move || {
+		let Ok(site) = site.read() else { return Ok(None) };
+		let Some(site) = site.as_ref() else { return Ok(None) };
+
+		let found = match path {
+			Some(path) => site.output(RelativePath::new(&path))?.map(|content| (path, content, StatusCode::OK)),
+			None => None,
+		};
+
+		Ok::<_, jake_lib::error::Error>(match found {
+			Some(found) => Some(found),
+			None => site.output(RelativePath::new(NOT_FOUND_PAGE))?.map(|content| (String::from(NOT_FOUND_PAGE), content, StatusCode::NOT_FOUND)),
+		})
+	}).await;
+
+	match output {
+		Ok(Ok(Some((path, content, status)))) => {
+			let mime = mime_guess::from_path(&path).first_or_octet_stream();
+			(status, [ (header::CONTENT_TYPE, mime.to_string()) ], Body::from(content.to_vec())).into_response()
+		},
+		Ok(Ok(None)) => StatusCode::NOT_FOUND.into_response(),
+		Ok(Err(e)) => {
+			let page = format!("<!DOCTYPE html><html><body>{}</body></html>", crate::overlay::render(&e));
+			e.print_error();
+
+			(StatusCode::INTERNAL_SERVER_ERROR, Html(page)).into_response()
+		},
+		Err(e) => (StatusCode::INTERNAL_SERVER_ERROR, e.to_string()).into_response(),
+	}
+}
+
+/// The site's 404 page, the same as paths with nothing behind them get.
+async fn not_found(outputs: &Outputs, request: Request) -> Response {
+	match outputs {
+		Outputs::Dir(dir) => {
+			let Ok(page) = dir.read().map(|dir| dir.join(NOT_FOUND_PAGE)) else {
+				return StatusCode::NOT_FOUND.into_response();
+			};
+
+			match ServeFile::new(page).try_call(request).await {
+				Ok(response) => (StatusCode::NOT_FOUND, response.map(Body::new)).into_response(),
+				Err(e) => (StatusCode::INTERNAL_SERVER_ERROR, e.to_string()).into_response(),
+			}
+		},
+		Outputs::Memory(site) => memory_output(site.clone(), None).await,
+	}
+}
+
+/// Takes the base path off requests, and serves `/about` from `about.html` or `about/index.html` like most static hosts do.
+async fn resolve(State(site): State<Arc<Site>>, mut request: Request) -> Result<Request, Response> {
+	let full_path = request.uri().path().to_owned();
+
+	let Ok(base_path) = site.base_path.read().map(|base_path| base_path.clone()) else {
+		return Err((StatusCode::INTERNAL_SERVER_ERROR, "The base path was lost to a panic").into_response());
+	};
+
+	let path = match full_path.strip_prefix(base_path.as_str()) {
+		_ if base_path.is_empty() => full_path.as_str(),
+		Some(rest) if rest.is_empty() || rest.starts_with('/') => rest,
+		_ if full_path == "/" => return Err(Redirect::temporary(&format!("{base_path}/")).into_response()),
+		_ => return Err(not_found(&site.outputs, request).await),
+	};
+
+	let rel_path = path.trim_start_matches('/');
+
+	let is_pretty = !rel_path.is_empty() && !path.ends_with('/') && Path::new(rel_path).extension().is_none();
+	let rewritten = is_pretty.then(|| [ format!("{rel_path}.html"), format!("{rel_path}/index.html") ].into_iter()
+		.find(|candidate| site.outputs.exists(candidate)))
+		.flatten();
+
+	let path = match rewritten {
+		Some(rewritten) => format!("/{rewritten}"),
+		None if path.is_empty() => String::from("/"),
+		None => path.to_owned(),
+	};
+
+	let path_and_query = match request.uri().query() {
+		Some(query) => format!("{path}?{query}"),
+		None => path,
+	};
+
+	if let Ok(uri) = path_and_query.parse::<Uri>() {
+		*request.uri_mut() = uri;
+	}
+
+	Ok(request)
+}
+
+/// Opens `url` with the system's default browser, if it can find it.
+pub fn open_browser(url: &str) {
+	let command = if cfg!(target_os = "macos") {
+		std::process::Command::new("open").arg(url).spawn()
+	} else if cfg!(target_os = "windows") {
+		std::process::Command::new("cmd").args([ "/C", "start", "", url ]).spawn()
+	} else {
+		std::process::Command::new("xdg-open").arg(url).spawn()
+	};
+
+	if let Err(e) = command {
+		eprintln!("Couldn't open the browser: {e}");
+	}
+}
@@ -3,10 +3,23 @@
 --- List of tags to be registered with Jake.
 --- 
 --- Functions should be added to this table under the
---- desired name of the tag.
----@type table<string, Tag>
+--- desired name of the tag.  
+--- They get the arguments of the tag.  
+--- A table with `keywords = true` takes `key: value` arguments too, as a table before the others.
+--- One with `raw = true` hands the function the tag's markup instead, to parse itself,
+--- as its tokens with single spaces between them. The markup still has to lex as Liquid,
+--- so something like a bare URL or `user/1234` is an error; quote it instead.
+---@type table<string, Tag | TagDef>
 TAGS = {}
 
+--- List of block tags to be registered with Jake.
+--- 
+--- Functions should be added to this table under the
+--- desired name of the block, which is closed with `end` and the name.  
+--- They get the rendered body of the block before its arguments.
+---@type table<string, Block | BlockDef>
+BLOCKS = {}
+
 --- List of filters to be registered with Jake.
 --- 
 --- Functions should be added to this table under the
@@ -14,6 +27,17 @@ TAGS = {}
 ---@type table<string, Filter>
 FILTERS = {}
 
+--- List of converters to be registered with Jake.
+--- 
+--- Converters are keyed by the source extension they handle, without leading dot.  
+--- They run on a file's content after its Liquid has been rendered,
+--- but before its post-processors and layouts.
+--- 
+--- Built-in converters exist for `md`, `markdown`, `scss` and `sass`.  
+--- Setting an entry to `false` disables conversion for that extension.
+---@type table<string, Converter | ConverterDef | false>
+CONVERTERS = {}
+
 --- Global data for the Jake project.  
 --- Includes config data, all files, paths, etc.
 ---@class SITE
@@ -31,10 +55,29 @@ FILTERS = {}
 ---@field plugins_dir string
 --- The directory where the layouts are located.
 ---@field layout_dir string
+--- The directory `include` and `render` tags look up partials in.
+---@field includes_dir string
+--- The directory data files are loaded from.
+---@field data_dir string
+--- The site globals from `jake.yml`, excluding Jake's own settings.  
+--- Changes made here are visible to templates.
+---@field config table<string, any>
+--- The contents of the data directory, keyed by path without extension.  
+--- Changes made here are visible to templates as `site.data`.
+---@field data table<string, any>
 --- The list of all source files in the project.
 ---@field files File[]
 SITE = {}
 
+--- The variables of the template being rendered, such as `page`, `site` or `forloop`.  
+--- Only set while a tag, block or filter runs.  
+--- Objects and arrays are views of the template's own, with array items from 1;
+--- call one, as in `CONTEXT.page.tags()`, for a plain copy to use with `pairs` or `ipairs`.  
+--- Assigning a field, or a field of one, sets it for the rest of the page, like `assign`.
+--- `page` and `site` can be changed or replaced too, though only as this page sees them.
+---@type table<string, any>?
+CONTEXT = nil
+
 --- A function to run at the end of the build process, after all files have been processed.
 ---@type fun()?
 POST_PROC = nil
@@ -74,6 +117,17 @@ File = {}
 ---@param ignore boolean?
 function File:ignore(ignore) end
 
+--- Options for paginating a file over one of the `site` listings.
+---@class PaginateOpts
+---@field collection string The listing to paginate, e.g. `"posts"` or `"pages"`.
+---@field per_page integer? How many items go on each page. Defaults to 10.
+---@field permalink string? Where later pages go, with `:num` standing in for the page number. Defaults to `page/:num/` next to the file.
+
+--- Render this file once per page of items, each with a `paginator` object in scope.  
+--- Equivalent to setting `paginate` in the file's front matter.
+---@param options PaginateOpts
+function File:paginate(options) end
+
 --- Create a new File object.  
 --- This can be used to generate files programmatically.
 ---@return File
@@ -152,7 +206,14 @@ function Path.parts(path) end
 ---@return Path
 function Path.new(path) end
 
----@alias Tag fun(...): any
+---@alias Converter fun(content: string, info: PostProcInfo): string
+--- A converter that changes the extension of its outputs.  
+--- If `ext` is omitted, the extension of the converter being replaced is kept.
+---@alias ConverterDef { func: Converter, ext: string? }
+---@alias Tag fun(...: any): any
+---@alias TagDef { func: Tag | fun(named_args: Args, pos_args...: any): any | fun(markup: string): any, keywords: boolean?, raw: boolean? }
+---@alias Block fun(body: string, ...: any): any
+---@alias BlockDef { func: Block | fun(body: string, named_args: Args, pos_args...: any): any | fun(body: string, markup: string): any, keywords: boolean?, raw: boolean? }
 ---@alias Filter fun(target: any, named_args: Args, pos_args...: any): any
 ---@alias Args table<string, any>
 ---@alias Iterator<T> fun(): T
@@ -168,3 +229,16 @@ function minify(content) end
 ---@param content string
 ---@return string
 function render(content) end
+
+---@class SassOpts
+--- Extra directories to search for `@use` and `@import`, relative to the project directory.  
+--- The project's `_sass` directory is always searched.
+---@field load_paths string[]?
+---@field indented boolean? `true` to use the indented `.sass` syntax rather than SCSS.
+---@field style "expanded" | "compressed" | nil
+
+--- Compiles Sass to CSS.
+---@param content string
+---@param opts SassOpts?
+---@return string
+function sass(content, opts) end
@@ -11,6 +11,13 @@ end
 
 -- print(package.path)
 
+---@param body string
+---@param kind string?
+function BLOCKS.callout(body, kind)
+	kind = kind or "note"
+	return '<div class="callout callout-' .. kind .. '"><strong>' .. kind .. ':</strong> ' .. body .. '</div>'
+end
+
 local titlecase = require "titlecase"
 
 ---@param input any
@@ -32,6 +39,29 @@ function TAGS.test_tag(name, ...)
 	return table.concat(result, "\n\n")
 end
 
+TAGS.youtube = {
+	keywords = true,
+	---@param args { id: string, width: integer?, height: integer? }
+	func = function(args)
+		local width, height = args.width or 560, args.height or 315
+		return '<iframe width="' .. width .. '" height="' .. height .. '" src="https://www.youtube.com/embed/' .. args.id .. '"></iframe>'
+	end,
+}
+
+-- Like a Jekyll plugin, this one picks its own markup apart.
+TAGS.shout = {
+	raw = true,
+	---@param markup string
+	func = function(markup)
+		return markup:upper():gsub("%s*>%s*", " -> ") .. "!"
+	end,
+}
+
+-- Tags can see the variables of the template they're in.
+function TAGS.page_info()
+	return CONTEXT.page.title .. " lives at " .. CONTEXT.page.url
+end
+
 -- A list containing 15 different fruits
 local fruits = {
 	"apple", "banana", "cherry", "date", "elderberry", "fig", "grape", "honeydew", "imbe", "jackfruit", "kiwi", "lemon", "mango", "nectarine", "orange"
@@ -55,11 +85,7 @@ end
 
 ---@type FilePostProcessFunc
 local function post_processor(content, info)
-	if info.source.ext == "md" then
-		content = render(content)
-	end
-
-	if not SITE.debug and info.is_final then
+	if not SITE.config.debug and info.is_final then
 		content = minify(content)
 	end
 
@@ -73,10 +99,6 @@ for i, file in ipairs(SITE.files) do
 		print("uwu on file " .. file.path.name)
 	end
 
-	if file.source.ext == "md" then
-		file.path.ext = "html"
-	end
-
 	if file.source.ext == "ts" then
 		file.path = Path.join("../generated", file.path)
 	end